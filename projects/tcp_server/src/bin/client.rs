@@ -0,0 +1,81 @@
+//! Sends requests to a `tcp_server binary` and prints the responses.
+//!
+//! `client [--addr HOST:PORT] [REQUEST]`, requests being `ping`, `echo <text>`,
+//! `double <n>`, `get <key>`, `set <key> <value>` and `del <key>`. Without one,
+//! requests are read from stdin, one per line.
+
+use std::{
+    env, error,
+    io::{self, BufRead},
+};
+
+use tcp_server::{
+    client::Client,
+    message::{Request, Response},
+};
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let mut addr = "localhost:7070".to_string();
+    if args.first().map(String::as_str) == Some("--addr") {
+        addr = args.get(1).ok_or("--addr needs a value")?.clone();
+        args.drain(..2);
+    }
+
+    let mut client = Client::connect(&addr)?;
+    if !args.is_empty() {
+        let request = parse(&args)?;
+        println!("{}", show(&client.request(&request)?));
+        return Ok(());
+    }
+
+    for line in io::stdin().lock().lines() {
+        let words = line?
+            .split_whitespace()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if words.is_empty() {
+            continue;
+        }
+        match parse(&words) {
+            Ok(request) => println!("{}", show(&client.request(&request)?)),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+    Ok(())
+}
+
+fn parse(words: &[String]) -> Result<Request, String> {
+    let bytes = |i: usize| words.get(i).map(|word| word.as_bytes().to_vec());
+    let request = match words[0].to_ascii_lowercase().as_str() {
+        "ping" => Some(Request::Ping),
+        "echo" => Some(Request::Echo(words[1..].join(" ").into_bytes())),
+        "double" => {
+            let n = words.get(1).ok_or("usage: double <n>")?;
+            let n = n.parse().map_err(|_| format!("'{n}' is not an integer"))?;
+            Some(Request::Double(n))
+        }
+        "get" => bytes(1).map(Request::Get),
+        "set" if words.len() > 2 => {
+            let value = words[2..].join(" ").into_bytes();
+            bytes(1).map(|key| Request::Set(key, value))
+        }
+        "set" => None,
+        "del" => bytes(1).map(Request::Del),
+        name => return Err(format!("unknown request '{name}'")),
+    };
+    request.ok_or_else(|| format!("missing arguments for {}", words[0]))
+}
+
+fn show(response: &Response) -> String {
+    match response {
+        Response::Pong => "PONG".to_string(),
+        Response::Data(data) | Response::Value(Some(data)) => {
+            String::from_utf8_lossy(data).into_owned()
+        }
+        Response::Integer(n) => n.to_string(),
+        Response::Value(None) => "(nil)".to_string(),
+        Response::Ok => "OK".to_string(),
+        Response::Error(message) => message.clone(),
+    }
+}
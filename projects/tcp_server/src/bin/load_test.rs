@@ -0,0 +1,176 @@
+//! Opens many connections to a `lines` server at once and reports how fast `DOUBLE`
+//! requests are answered.
+//!
+//! `load_test [--addr HOST:PORT] [--connections N] [--requests N]`. Without `--addr`
+//! both backends are started in-process and measured one after the other.
+
+use std::{
+    env, error, fmt, io,
+    net::SocketAddr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tcp_server::{
+    command::Dispatcher,
+    server::{Config, Server},
+    tokio_server::TokioServer,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+struct Options {
+    addr: Option<SocketAddr>,
+    connections: usize,
+    requests: usize,
+}
+
+fn options() -> Result<Options, Box<dyn error::Error>> {
+    let mut options = Options {
+        addr: None,
+        connections: 2000,
+        requests: 20,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("{arg} needs a value"))?;
+        match arg.as_str() {
+            "--addr" => options.addr = Some(value.parse()?),
+            "--connections" => options.connections = value.parse()?,
+            "--requests" => options.requests = value.parse()?,
+            _ => return Err(format!("unknown option {arg}").into()),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let options = options()?;
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    if let Some(addr) = options.addr {
+        let report = runtime.block_on(load(addr, &options));
+        println!("{addr}: {report}");
+        return Ok(());
+    }
+
+    let config = Config {
+        max_clients: usize::MAX,
+        rate_limit: None,
+        log_clients: false,
+        ..Config::default()
+    };
+
+    let server = Server::bind("127.0.0.1:0", config)?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || server.run(Arc::new(Dispatcher::default())));
+    let report = runtime.block_on(load(addr, &options));
+    println!("threads: {report}");
+
+    let report = runtime.block_on(async {
+        let server = TokioServer::bind("127.0.0.1:0", config).await?;
+        let addr = server.local_addr()?;
+        tokio::spawn(async move { server.run(Arc::new(Dispatcher::default())).await });
+        Ok::<_, io::Error>(load(addr, &options).await)
+    })?;
+    println!("tokio:   {report}");
+    Ok(())
+}
+
+struct Report {
+    connections: usize,
+    /// Connections that failed, and why the first one did.
+    failed: (usize, Option<io::Error>),
+    elapsed: Duration,
+    /// Per request, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requests = self.latencies.len();
+        let percentile = |p: usize| {
+            self.latencies
+                .get((requests * p / 100).min(requests.saturating_sub(1)))
+                .copied()
+                .unwrap_or_default()
+        };
+        write!(
+            f,
+            "{requests} requests over {} connections in {:.2?}, {:.0} req/s, \
+             latency p50 {:.2?} p90 {:.2?} p99 {:.2?} max {:.2?}",
+            self.connections - self.failed.0,
+            self.elapsed,
+            requests as f64 / self.elapsed.as_secs_f64(),
+            percentile(50),
+            percentile(90),
+            percentile(99),
+            self.latencies.last().copied().unwrap_or_default(),
+        )?;
+        if let (failed, Some(e)) = &self.failed {
+            write!(f, ", {failed} connections failed ({e})")?;
+        }
+        Ok(())
+    }
+}
+
+async fn load(addr: SocketAddr, options: &Options) -> Report {
+    let start = Instant::now();
+    let mut clients = JoinSet::new();
+    for id in 0..options.connections {
+        clients.spawn(client(addr, id, options.requests));
+    }
+
+    let mut latencies = Vec::with_capacity(options.connections * options.requests);
+    let mut failed = (0, None);
+    while let Some(result) = clients.join_next().await {
+        match result.map_err(io::Error::other).and_then(|result| result) {
+            Ok(client_latencies) => latencies.extend(client_latencies),
+            Err(e) => {
+                failed.0 += 1;
+                failed.1.get_or_insert(e);
+            }
+        }
+    }
+    latencies.sort();
+
+    Report {
+        connections: options.connections,
+        failed,
+        elapsed: start.elapsed(),
+        latencies,
+    }
+}
+
+/// Sends `requests` requests one after the other, checking each reply.
+async fn client(addr: SocketAddr, id: usize, requests: usize) -> io::Result<Vec<Duration>> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    let mut replies = BufReader::new(reader).lines();
+    let mut latencies = Vec::with_capacity(requests);
+
+    for n in 0..requests {
+        let value = (id * requests + n) as i64;
+        let sent = Instant::now();
+        writer
+            .write_all(format!("DOUBLE {value}\n").as_bytes())
+            .await?;
+        let reply = replies
+            .next_line()
+            .await?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        if reply != (value * 2).to_string() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("DOUBLE {value} got {reply}"),
+            ));
+        }
+        latencies.push(sent.elapsed());
+    }
+    Ok(latencies)
+}
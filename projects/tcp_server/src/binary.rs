@@ -0,0 +1,113 @@
+use std::{io, net::TcpStream, sync::Arc};
+
+use crate::{
+    command::CommandError,
+    frame::{read_frame, skip_payload, write_frame, FrameError},
+    limit::RateLimiter,
+    message::{Request, Response},
+    server::{Config, Handler},
+    store::Store,
+};
+
+/// Serves framed `Request`s against a `Store`.
+pub struct BinaryHandler {
+    store: Arc<Store>,
+}
+
+impl BinaryHandler {
+    pub fn new(store: Arc<Store>) -> Self {
+        Self { store }
+    }
+}
+
+impl Handler for BinaryHandler {
+    fn handle(&self, stream: TcpStream, config: &Config) -> io::Result<()> {
+        handle_binary_client(stream, &self.store, config)
+    }
+
+    fn busy_message(&self) -> Vec<u8> {
+        let mut frame = Vec::new();
+        let response = Response::Error(CommandError::Busy.to_string());
+        write_frame(&mut frame, &response.encode()).expect("writing to a Vec");
+        frame
+    }
+}
+
+/// Answers each request frame until the client disconnects. Requests that can't be
+/// decoded, or are over `Config::max_frame_len`, get an error response.
+pub fn handle_binary_client(stream: TcpStream, store: &Store, config: &Config) -> io::Result<()> {
+    let (mut reader, mut writer) = (&stream, &stream);
+    let mut limiter = RateLimiter::new(config.rate_limit);
+
+    loop {
+        let response = match read_frame(&mut reader, config.max_frame_len) {
+            Ok(None) => return Ok(()),
+            Ok(Some(_)) if !limiter.allow() => Err(CommandError::RateLimited),
+            Ok(Some(frame)) => Request::decode(&frame)
+                .map_err(|e| CommandError::BadRequest(e.to_string()))
+                .and_then(|request| execute(store, request)),
+            Err(FrameError::Io(e)) => return Err(e),
+            Err(e @ FrameError::TooLarge(len)) => match skip_payload(&mut reader, len) {
+                Ok(()) => Err(CommandError::BadRequest(e.to_string())),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            },
+        };
+
+        let response = response.unwrap_or_else(|e| Response::Error(e.to_string()));
+        write_frame(&mut writer, &response.encode())?;
+    }
+}
+
+pub fn execute(store: &Store, request: Request) -> Result<Response, CommandError> {
+    Ok(match request {
+        Request::Ping => Response::Pong,
+        Request::Echo(data) => Response::Data(data),
+        Request::Double(n) => Response::Integer(n.checked_mul(2).ok_or(CommandError::Overflow)?),
+        Request::Get(key) => Response::Value(store.get(&key)),
+        Request::Set(key, value) => {
+            store.set(key, value, None);
+            Response::Ok
+        }
+        Request::Del(key) => Response::Integer(store.del(&key) as i64),
+    })
+}
+
+#[test]
+fn pure_commands() {
+    let store = Store::new();
+    assert_eq!(execute(&store, Request::Ping), Ok(Response::Pong));
+    assert_eq!(
+        execute(&store, Request::Echo(b"\0\xff".to_vec())),
+        Ok(Response::Data(b"\0\xff".to_vec()))
+    );
+    assert_eq!(
+        execute(&store, Request::Double(-21)),
+        Ok(Response::Integer(-42))
+    );
+    assert_eq!(
+        execute(&store, Request::Double(i64::MIN)),
+        Err(CommandError::Overflow)
+    );
+}
+
+#[test]
+fn store_commands() {
+    let store = Store::new();
+    let get = |store: &Store| execute(store, Request::Get(b"key".to_vec()));
+    let del = |store: &Store| execute(store, Request::Del(b"key".to_vec()));
+
+    assert_eq!(get(&store), Ok(Response::Value(None)));
+    assert_eq!(
+        execute(&store, Request::Set(b"key".to_vec(), b"one".to_vec())),
+        Ok(Response::Ok)
+    );
+    // Overwrites
+    execute(&store, Request::Set(b"key".to_vec(), b"two".to_vec())).unwrap();
+    assert_eq!(get(&store), Ok(Response::Value(Some(b"two".to_vec()))));
+    assert_eq!(store.get(b"key"), Some(b"two".to_vec()));
+
+    assert_eq!(del(&store), Ok(Response::Integer(1)));
+    assert_eq!(del(&store), Ok(Response::Integer(0)));
+    assert_eq!(get(&store), Ok(Response::Value(None)));
+}
@@ -0,0 +1,442 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    codec::{write_line, CodecError, LineReader},
+    limit::RateLimiter,
+    server::{Config, Handler},
+};
+
+/// Lines queued for a client before it starts missing messages. A client that stops
+/// reading falls behind on its own, without stalling the room.
+const OUTBOX_CAPACITY: usize = 256;
+
+const MAX_NAME_LEN: usize = 32;
+
+const HELP: &str = "* /join <room>, /leave, /who, /msg <nick> <text>, /quit";
+
+/// Clients pick a nickname, join a room and talk to everyone else in it.
+#[derive(Default)]
+pub struct ChatServer {
+    state: Mutex<State>,
+    next_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    clients: HashMap<u64, Client>,
+    /// Lowercased, so nicknames are unique regardless of case.
+    nicks: HashMap<String, u64>,
+    rooms: HashMap<String, BTreeSet<u64>>,
+}
+
+struct Client {
+    nick: String,
+    room: Option<String>,
+    outbox: SyncSender<String>,
+}
+
+impl ChatServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chats with a client until it disconnects or sends `/quit`, then takes it out of
+    /// its room.
+    pub fn handle_client(&self, stream: TcpStream, config: &Config) -> io::Result<()> {
+        let mut lines = LineReader::new(&stream, config.max_line_len);
+        let (outbox, inbox) = mpsc::sync_channel(OUTBOX_CAPACITY);
+        let writer = {
+            let stream = stream.try_clone()?;
+            thread::spawn(move || write_outbox(stream, inbox))
+        };
+
+        let result = self.session(&mut lines, &outbox, config);
+        // The writer stops once every sender is gone, after the last queued line
+        drop(outbox);
+        let _ = writer.join();
+        result
+    }
+
+    fn session(
+        &self,
+        lines: &mut LineReader<&TcpStream>,
+        outbox: &SyncSender<String>,
+        config: &Config,
+    ) -> io::Result<()> {
+        send(outbox, "* welcome, pick a nickname");
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        loop {
+            let Some(nick) = next_line(lines, outbox)? else {
+                return Ok(());
+            };
+            let mut state = self.state.lock().unwrap();
+            match state.register(id, nick.trim(), outbox.clone()) {
+                Ok(()) => break,
+                Err(reason) => send(outbox, format!("* {reason}, pick another")),
+            }
+        }
+
+        let result = self.chat(id, lines, outbox, RateLimiter::new(config.rate_limit));
+        self.state.lock().unwrap().disconnect(id);
+        result
+    }
+
+    fn chat(
+        &self,
+        id: u64,
+        lines: &mut LineReader<&TcpStream>,
+        outbox: &SyncSender<String>,
+        mut limiter: RateLimiter,
+    ) -> io::Result<()> {
+        while let Some(line) = next_line(lines, outbox)? {
+            if !limiter.allow() {
+                send(outbox, "* slow down, message dropped");
+                continue;
+            }
+            let mut state = self.state.lock().unwrap();
+            let Some(command) = line.strip_prefix('/') else {
+                state.say(id, &line);
+                continue;
+            };
+
+            let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+            let args = args.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "join" => state.join(id, args),
+                "leave" => state.leave(id),
+                "who" => state.who(id),
+                "msg" => state.private(id, args),
+                "help" => state.reply(id, HELP),
+                "quit" => {
+                    state.reply(id, "* bye");
+                    return Ok(());
+                }
+                _ => state.reply(id, format!("* unknown command /{name}, try /help")),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Handler for ChatServer {
+    fn handle(&self, stream: TcpStream, config: &Config) -> io::Result<()> {
+        self.handle_client(stream, config)
+    }
+
+    fn busy_message(&self) -> Vec<u8> {
+        b"* too many people here, try again later\n".to_vec()
+    }
+}
+
+impl State {
+    fn register(&mut self, id: u64, nick: &str, outbox: SyncSender<String>) -> Result<(), String> {
+        if !valid_name(nick) {
+            return Err(format!(
+                "nicknames are 1-{MAX_NAME_LEN} letters, digits, _ or -"
+            ));
+        }
+        if self.nicks.contains_key(&nick.to_lowercase()) {
+            return Err(format!("nickname {nick} is taken"));
+        }
+
+        send(&outbox, format!("* hi {nick}, /join a room or /help"));
+        self.nicks.insert(nick.to_lowercase(), id);
+        let client = Client {
+            nick: nick.to_string(),
+            room: None,
+            outbox,
+        };
+        self.clients.insert(id, client);
+        Ok(())
+    }
+
+    fn disconnect(&mut self, id: u64) {
+        self.leave_room(id);
+        if let Some(client) = self.clients.remove(&id) {
+            self.nicks.remove(&client.nick.to_lowercase());
+        }
+    }
+
+    fn say(&self, id: u64, text: &str) {
+        let client = &self.clients[&id];
+        match &client.room {
+            Some(room) => self.broadcast(room, id, &format!("[#{room}] {}: {text}", client.nick)),
+            None => self.reply(id, "* join a room first with /join <room>"),
+        }
+    }
+
+    fn join(&mut self, id: u64, room: &str) {
+        let room = room.strip_prefix('#').unwrap_or(room).to_lowercase();
+        if !valid_name(&room) {
+            return self.reply(
+                id,
+                format!("* room names are 1-{MAX_NAME_LEN} letters, digits, _ or -"),
+            );
+        }
+        if self.clients[&id].room.as_ref() == Some(&room) {
+            return self.reply(id, format!("* already in #{room}"));
+        }
+
+        self.leave_room(id);
+        let nick = &self.clients[&id].nick;
+        self.broadcast(&room, id, &format!("* {nick} joined #{room}"));
+        self.reply(id, format!("* joined #{room}"));
+        self.rooms.entry(room.clone()).or_default().insert(id);
+        self.clients.get_mut(&id).unwrap().room = Some(room);
+    }
+
+    fn leave(&mut self, id: u64) {
+        match self.leave_room(id) {
+            Some(room) => self.reply(id, format!("* left #{room}")),
+            None => self.reply(id, "* not in a room"),
+        }
+    }
+
+    /// Takes the client out of its room, telling the others. Empty rooms go away.
+    fn leave_room(&mut self, id: u64) -> Option<String> {
+        let room = self.clients.get_mut(&id)?.room.take()?;
+        let members = self.rooms.get_mut(&room)?;
+        members.remove(&id);
+        if members.is_empty() {
+            self.rooms.remove(&room);
+        } else {
+            let nick = &self.clients[&id].nick;
+            self.broadcast(&room, id, &format!("* {nick} left #{room}"));
+        }
+        Some(room)
+    }
+
+    fn who(&self, id: u64) {
+        let Some(room) = &self.clients[&id].room else {
+            return self.reply(id, "* not in a room");
+        };
+        let mut nicks = self.rooms[room]
+            .iter()
+            .map(|member| self.clients[member].nick.as_str())
+            .collect::<Vec<_>>();
+        nicks.sort_by_key(|nick| nick.to_lowercase());
+        self.reply(id, format!("* in #{room}: {}", nicks.join(", ")));
+    }
+
+    fn private(&self, id: u64, args: &str) {
+        let Some((to, text)) = args.split_once(' ') else {
+            return self.reply(id, "* usage: /msg <nick> <text>");
+        };
+        let Some(&to_id) = self.nicks.get(&to.to_lowercase()) else {
+            return self.reply(id, format!("* no such nickname: {to}"));
+        };
+
+        let text = text.trim();
+        let from = &self.clients[&id].nick;
+        self.reply(to_id, format!("[pm from {from}] {text}"));
+        self.reply(id, format!("[pm to {}] {text}", self.clients[&to_id].nick));
+    }
+
+    fn reply(&self, id: u64, line: impl Into<String>) {
+        if let Some(client) = self.clients.get(&id) {
+            send(&client.outbox, line);
+        }
+    }
+
+    /// To everyone in `room` but `sender`.
+    fn broadcast(&self, room: &str, sender: u64, line: &str) {
+        for &member in self.rooms.get(room).into_iter().flatten() {
+            if member != sender {
+                self.reply(member, line);
+            }
+        }
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LEN).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Drops the line if the client is too far behind, or gone.
+fn send(outbox: &SyncSender<String>, line: impl Into<String>) {
+    let _ = outbox.try_send(line.into());
+}
+
+/// The next non-blank line, `None` once the client disconnects. Lines that can't be
+/// read are reported to the client and skipped.
+fn next_line(
+    lines: &mut LineReader<&TcpStream>,
+    outbox: &SyncSender<String>,
+) -> io::Result<Option<String>> {
+    loop {
+        match lines.read_line() {
+            Ok(Some(line)) if line.trim().is_empty() => {}
+            Ok(line) => return Ok(line),
+            Err(CodecError::Io(e)) => return Err(e),
+            Err(e) => send(outbox, format!("* {e}")),
+        }
+    }
+}
+
+fn write_outbox(mut stream: TcpStream, inbox: Receiver<String>) {
+    for line in inbox {
+        if write_line(&mut stream, &line).is_err() {
+            return;
+        }
+    }
+}
+
+/// A chat server on a loopback port.
+#[cfg(test)]
+fn spawn_chat() -> std::net::SocketAddr {
+    use std::{net::TcpListener, sync::Arc};
+
+    let chat = Arc::new(ChatServer::new());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let chat = Arc::clone(&chat);
+            thread::spawn(move || chat.handle_client(stream.unwrap(), &Config::default()));
+        }
+    });
+    addr
+}
+
+#[cfg(test)]
+struct Peer(io::BufReader<TcpStream>);
+
+#[cfg(test)]
+impl Peer {
+    /// Connects and picks `nick`, leaving the reply to it unread.
+    fn connect(addr: std::net::SocketAddr, nick: &str) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut peer = Peer(io::BufReader::new(stream));
+        peer.expect("* welcome, pick a nickname");
+        peer.send(nick);
+        peer
+    }
+
+    /// Connects as `nick` and joins `room`.
+    fn join(addr: std::net::SocketAddr, nick: &str, room: &str) -> Self {
+        let mut peer = Self::connect(addr, nick);
+        peer.expect(&format!("* hi {nick}, /join a room or /help"));
+        peer.send(&format!("/join {room}"));
+        peer.expect(&format!("* joined {room}"));
+        peer
+    }
+
+    fn send(&mut self, line: &str) {
+        write_line(self.0.get_mut(), line).unwrap();
+    }
+
+    fn expect(&mut self, expected: &str) {
+        use std::io::BufRead;
+
+        let mut line = String::new();
+        self.0.read_line(&mut line).unwrap();
+        assert_eq!(line.trim_end(), expected);
+    }
+}
+
+#[test]
+fn nicknames() {
+    let addr = spawn_chat();
+    let mut alice = Peer::connect(addr, "alice");
+    alice.expect("* hi alice, /join a room or /help");
+    let mut bob = Peer::connect(addr, "ALICE");
+    bob.expect("* nickname ALICE is taken, pick another");
+    bob.send("bad nick");
+    bob.expect("* nicknames are 1-32 letters, digits, _ or -, pick another");
+    bob.send("bob");
+    bob.expect("* hi bob, /join a room or /help");
+}
+
+#[test]
+fn rooms() {
+    let addr = spawn_chat();
+    let mut alice = Peer::connect(addr, "alice");
+    alice.expect("* hi alice, /join a room or /help");
+    alice.send("hello?");
+    alice.expect("* join a room first with /join <room>");
+    alice.send("/join #rust");
+    alice.expect("* joined #rust");
+    let mut bob = Peer::join(addr, "bob", "#rust");
+    alice.expect("* bob joined #rust");
+    let mut carol = Peer::join(addr, "carol", "#go");
+
+    // Only the rest of the room hears it
+    alice.send("hi all");
+    bob.expect("[#rust] alice: hi all");
+    alice.send("/who");
+    alice.expect("* in #rust: alice, bob");
+    carol.send("/who");
+    carol.expect("* in #go: carol");
+
+    bob.send("/leave");
+    bob.expect("* left #rust");
+    alice.expect("* bob left #rust");
+    bob.send("/leave");
+    bob.expect("* not in a room");
+    alice.send("/who");
+    alice.expect("* in #rust: alice");
+}
+
+#[test]
+fn disconnecting_leaves_and_frees_the_nickname() {
+    use std::net::Shutdown;
+
+    let addr = spawn_chat();
+    let mut alice = Peer::join(addr, "alice", "#rust");
+    let carol = Peer::join(addr, "carol", "#rust");
+    alice.expect("* carol joined #rust");
+
+    carol.0.get_ref().shutdown(Shutdown::Both).unwrap();
+    alice.expect("* carol left #rust");
+    alice.send("/who");
+    alice.expect("* in #rust: alice");
+    // And frees the nickname
+    let mut carol = Peer::connect(addr, "carol");
+    carol.expect("* hi carol, /join a room or /help");
+}
+
+#[test]
+fn private_messages() {
+    let addr = spawn_chat();
+    let mut alice = Peer::join(addr, "alice", "#rust");
+    let mut carol = Peer::connect(addr, "carol");
+    carol.expect("* hi carol, /join a room or /help");
+
+    // Without sharing a room, whatever the case of the nickname
+    carol.send("/msg Alice psst");
+    carol.expect("[pm to alice] psst");
+    alice.expect("[pm from carol] psst");
+    carol.send("/msg dave hi");
+    carol.expect("* no such nickname: dave");
+    carol.send("/msg alice");
+    carol.expect("* usage: /msg <nick> <text>");
+}
+
+#[test]
+fn quit_and_unknown_commands() {
+    let addr = spawn_chat();
+    let mut bob = Peer::connect(addr, "bob");
+    bob.expect("* hi bob, /join a room or /help");
+    bob.send("/dance");
+    bob.expect("* unknown command /dance, try /help");
+    bob.send("/quit");
+    bob.expect("* bye");
+    let mut rest = String::new();
+    assert_eq!(io::BufRead::read_line(&mut bob.0, &mut rest).unwrap(), 0);
+}
@@ -0,0 +1,213 @@
+use std::{
+    fmt, io,
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    frame::{read_frame, skip_payload, write_frame, FrameError, DEFAULT_MAX_FRAME},
+    message::{DecodeError, Request, Response},
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Frame(FrameError),
+    Decode(DecodeError),
+    /// The server answered with an error.
+    Server(String),
+    /// The server answered, but not the way the request calls for.
+    UnexpectedResponse(Response),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "i/o error: {e}"),
+            ClientError::Frame(e) => write!(f, "{e}"),
+            ClientError::Decode(e) => write!(f, "invalid response: {e}"),
+            ClientError::Server(message) => write!(f, "{message}"),
+            ClientError::UnexpectedResponse(response) => {
+                write!(f, "unexpected response {response:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+/// A connection to a binary `tcp_server`, one request at a time.
+pub struct Client {
+    stream: TcpStream,
+    max_frame_len: u32,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            max_frame_len: DEFAULT_MAX_FRAME,
+        })
+    }
+
+    /// Largest response accepted.
+    pub fn with_max_frame_len(mut self, max_frame_len: u32) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Sends a request and waits for its response. Error responses are returned as
+    /// they are, not as `ClientError::Server`. A response over `max_frame_len` is
+    /// skipped, the client can still be used after it.
+    pub fn request(&mut self, request: &Request) -> Result<Response, ClientError> {
+        write_frame(&mut self.stream, &request.encode())?;
+        let frame = match read_frame(&mut self.stream, self.max_frame_len) {
+            Err(FrameError::TooLarge(len)) => {
+                skip_payload(&mut self.stream, len)?;
+                return Err(ClientError::Frame(FrameError::TooLarge(len)));
+            }
+            frame => frame.map_err(ClientError::Frame)?,
+        }
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        Response::decode(&frame).map_err(ClientError::Decode)
+    }
+
+    pub fn ping(&mut self) -> Result<(), ClientError> {
+        match self.call(&Request::Ping)? {
+            Response::Pong => Ok(()),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub fn echo(&mut self, data: &[u8]) -> Result<Vec<u8>, ClientError> {
+        match self.call(&Request::Echo(data.to_vec()))? {
+            Response::Data(data) => Ok(data),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub fn double(&mut self, n: i64) -> Result<i64, ClientError> {
+        match self.call(&Request::Double(n))? {
+            Response::Integer(doubled) => Ok(doubled),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, ClientError> {
+        match self.call(&Request::Get(key.to_vec()))? {
+            Response::Value(value) => Ok(value),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), ClientError> {
+        match self.call(&Request::Set(key.to_vec(), value.to_vec()))? {
+            Response::Ok => Ok(()),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Whether the key was there.
+    pub fn del(&mut self, key: &[u8]) -> Result<bool, ClientError> {
+        match self.call(&Request::Del(key.to_vec()))? {
+            Response::Integer(deleted) => Ok(deleted == 1),
+            other => Err(ClientError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// `request`, with error responses as `ClientError::Server`.
+    fn call(&mut self, request: &Request) -> Result<Response, ClientError> {
+        match self.request(request)? {
+            Response::Error(message) => Err(ClientError::Server(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+/// A binary protocol server on a loopback port, taking frames of up to 64 bytes.
+#[cfg(test)]
+fn spawn_binary_server() -> std::net::SocketAddr {
+    use crate::{
+        binary::BinaryHandler,
+        server::{Config, Server},
+        store::Store,
+    };
+    use std::{sync::Arc, thread};
+
+    let config = Config {
+        max_frame_len: 64,
+        log_clients: false,
+        ..Config::default()
+    };
+    let server = Server::bind("127.0.0.1:0", config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(Arc::new(BinaryHandler::new(Arc::new(Store::new())))));
+    addr
+}
+
+#[test]
+fn binary_over_loopback() {
+    let mut client = Client::connect(spawn_binary_server()).unwrap();
+    client.ping().unwrap();
+    assert_eq!(client.echo(b"\0\r\n\xff").unwrap(), b"\0\r\n\xff");
+    assert_eq!(client.double(-21).unwrap(), -42);
+    assert_eq!(
+        client.double(i64::MAX).unwrap_err().to_string(),
+        "ERR OVERFLOW result doesn't fit in 64 bits"
+    );
+}
+
+#[test]
+fn shared_store() {
+    let addr = spawn_binary_server();
+    let mut client = Client::connect(addr).unwrap();
+    assert_eq!(client.get(b"key").unwrap(), None);
+    client.set(b"key", b"\x01\x02").unwrap();
+    assert_eq!(client.get(b"key").unwrap(), Some(b"\x01\x02".to_vec()));
+
+    let mut other = Client::connect(addr).unwrap();
+    assert!(other.del(b"key").unwrap());
+    assert!(!client.del(b"key").unwrap());
+}
+
+#[test]
+fn bad_requests() {
+    let mut client = Client::connect(spawn_binary_server()).unwrap();
+
+    // A frame that isn't a request
+    write_frame(&mut client.stream, b"\x42").unwrap();
+    let frame = read_frame(&mut client.stream, 64).unwrap().unwrap();
+    assert_eq!(
+        Response::decode(&frame),
+        Ok(Response::Error(
+            "ERR BAD_REQUEST unknown message tag 0x42".to_string()
+        ))
+    );
+    client.ping().unwrap();
+
+    // So is one too large, without buffering it
+    assert_eq!(
+        client.request(&Request::Echo(vec![0; 100])).unwrap(),
+        Response::Error("ERR BAD_REQUEST frame of 105 bytes is too large".to_string())
+    );
+    assert_eq!(client.double(2).unwrap(), 4);
+}
+
+#[test]
+fn response_too_large() {
+    let client = Client::connect(spawn_binary_server()).unwrap();
+    let mut client = client.with_max_frame_len(8);
+    assert!(matches!(
+        client.echo(b"more than 8 bytes"),
+        Err(ClientError::Frame(FrameError::TooLarge(22)))
+    ));
+    // Which doesn't leave the next response out of step
+    client.ping().unwrap();
+}
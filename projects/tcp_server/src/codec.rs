@@ -0,0 +1,242 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead};
+
+/// Longest line accepted by default, in bytes.
+pub const DEFAULT_MAX_LINE: usize = 4096;
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+    /// The line was discarded up to its newline, the next one can be read.
+    LineTooLong,
+    InvalidUtf8,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "i/o error: {e}"),
+            CodecError::LineTooLong => write!(f, "line too long"),
+            CodecError::InvalidUtf8 => write!(f, "line is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+/// Splits a byte stream into `\n` terminated lines, however they're spread across
+/// reads. A `\r` before the `\n` is dropped too.
+pub struct LineReader<R> {
+    reader: BufReader<R>,
+    framer: Framer,
+}
+
+impl<R: Read> LineReader<R> {
+    /// `max_len` doesn't count the line terminator.
+    pub fn new(reader: R, max_len: usize) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            framer: Framer::new(max_len),
+        }
+    }
+
+    /// The next line without its terminator, `None` once the stream ends. A last line
+    /// missing its `\n` is still returned.
+    pub fn read_line(&mut self) -> Result<Option<String>, CodecError> {
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if available.is_empty() {
+                return self.framer.finish().transpose();
+            }
+
+            let (consumed, line) = self.framer.feed(available);
+            self.reader.consume(consumed);
+            if let Some(line) = line {
+                return line.map(Some);
+            }
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.reader.get_ref()
+    }
+
+    /// Bytes already read from the stream but not returned as a line yet.
+    pub fn buffer(&self) -> &[u8] {
+        self.reader.buffer()
+    }
+}
+
+/// `LineReader` for tokio streams.
+pub struct AsyncLineReader<R> {
+    reader: tokio::io::BufReader<R>,
+    framer: Framer,
+}
+
+impl<R: AsyncRead + Unpin> AsyncLineReader<R> {
+    pub fn new(reader: R, max_len: usize) -> Self {
+        Self {
+            reader: tokio::io::BufReader::new(reader),
+            framer: Framer::new(max_len),
+        }
+    }
+
+    /// Cancel safe, a line partly read when the future is dropped isn't lost.
+    pub async fn read_line(&mut self) -> Result<Option<String>, CodecError> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                return self.framer.finish().transpose();
+            }
+
+            let (consumed, line) = self.framer.feed(available);
+            self.reader.consume(consumed);
+            if let Some(line) = line {
+                return line.map(Some);
+            }
+        }
+    }
+}
+
+/// The framing shared by both readers, fed whatever bytes are available.
+struct Framer {
+    max_len: usize,
+    line: Vec<u8>,
+    too_long: bool,
+}
+
+impl Framer {
+    fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            line: Vec::new(),
+            too_long: false,
+        }
+    }
+
+    /// How many bytes of `available` were used, and the line if one was completed.
+    fn feed(&mut self, available: &[u8]) -> (usize, Option<Result<String, CodecError>>) {
+        let newline = available.iter().position(|&b| b == b'\n');
+        let content = &available[..newline.unwrap_or(available.len())];
+        // Past the limit the rest of the line is skipped, not buffered
+        if !self.too_long {
+            // A trailing \r may be the start of the terminator, even one whose \n
+            // hasn't been read yet
+            let pending_cr = match content.last() {
+                Some(&last) => last == b'\r',
+                None => self.line.ends_with(b"\r"),
+            };
+            if self.line.len() + content.len() - usize::from(pending_cr) > self.max_len {
+                self.too_long = true;
+                self.line = Vec::new();
+            } else {
+                self.line.extend_from_slice(content);
+            }
+        }
+
+        match newline {
+            Some(i) => (i + 1, Some(self.take())),
+            None => (available.len(), None),
+        }
+    }
+
+    /// At the end of the stream, the last line if it had no `\n`.
+    fn finish(&mut self) -> Option<Result<String, CodecError>> {
+        (!self.line.is_empty() || self.too_long).then(|| self.take())
+    }
+
+    fn take(&mut self) -> Result<String, CodecError> {
+        let mut line = std::mem::take(&mut self.line);
+        if std::mem::take(&mut self.too_long) {
+            return Err(CodecError::LineTooLong);
+        }
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| CodecError::InvalidUtf8)
+    }
+}
+
+/// In a single write, so an unbuffered socket sends the line as one segment.
+pub fn write_line<W: Write>(writer: &mut W, line: &str) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(line.len() + 1);
+    bytes.extend_from_slice(line.as_bytes());
+    bytes.push(b'\n');
+    writer.write_all(&bytes)
+}
+
+/// Hands out one chunk per read, like a socket would.
+#[cfg(test)]
+struct Chunks(Vec<&'static [u8]>);
+
+#[cfg(test)]
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Ok(0);
+        }
+        let chunk = self.0.remove(0);
+        buf[..chunk.len()].copy_from_slice(chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[test]
+fn lines_across_reads() {
+    let chunks = Chunks(vec![b"PI", b"NG\r\nECHO a", b" b\nDOUBLE 2\nQUIT\n"]);
+    let mut lines = LineReader::new(chunks, 10);
+    let mut next = || lines.read_line().unwrap();
+
+    assert_eq!(next(), Some("PING".to_string()));
+    assert_eq!(next(), Some("ECHO a b".to_string()));
+    assert_eq!(next(), Some("DOUBLE 2".to_string()));
+    assert_eq!(next(), Some("QUIT".to_string()));
+    assert_eq!(next(), None);
+}
+
+#[test]
+fn bad_lines_are_skipped() {
+    let chunks = Chunks(vec![
+        b"0123456789abcdef",
+        b"xyz\nnext\n",
+        b"\xff\xfe\n",
+        b"no newline",
+    ]);
+    let mut lines = LineReader::new(chunks, 10);
+    let mut next = || lines.read_line().map_err(|e| e.to_string());
+
+    assert_eq!(next(), Err("line too long".to_string()));
+    assert_eq!(next(), Ok(Some("next".to_string())));
+    assert_eq!(next(), Err("line is not valid UTF-8".to_string()));
+    // The last line is still returned without its newline
+    assert_eq!(next(), Ok(Some("no newline".to_string())));
+    assert_eq!(next(), Ok(None));
+}
+
+#[test]
+fn terminator_isnt_counted() {
+    let mut lines = LineReader::new(&b"0123456789\r\n"[..], 10);
+    assert_eq!(lines.read_line().unwrap(), Some("0123456789".to_string()));
+
+    // Nor when the \r comes in a read of its own, apart from the \n
+    let mut lines = LineReader::new(
+        Chunks(vec![b"0123456789", b"\r", b"\n", b"0123456789\r", b"x\n"]),
+        10,
+    );
+    assert_eq!(lines.read_line().unwrap(), Some("0123456789".to_string()));
+    assert!(matches!(lines.read_line(), Err(CodecError::LineTooLong)));
+}
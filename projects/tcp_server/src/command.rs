@@ -0,0 +1,239 @@
+use std::{collections::BTreeMap, fmt};
+
+/// What a command sends back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Line(String),
+    Lines(Vec<String>),
+    /// Sent, then the connection is closed.
+    Close(String),
+}
+
+impl Reply {
+    /// The reply as sent, each line ending in `\n`.
+    pub fn to_text(&self) -> String {
+        match self {
+            Reply::Line(line) | Reply::Close(line) => format!("{line}\n"),
+            Reply::Lines(lines) => lines.iter().map(|line| format!("{line}\n")).collect(),
+        }
+    }
+
+    /// Whether the connection is closed once the reply is sent.
+    pub fn closes(&self) -> bool {
+        matches!(self, Reply::Close(_))
+    }
+}
+
+/// Sent to the client as `ERR <CODE> <message>`, the connection stays open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidArgument(String),
+    /// A binary request that couldn't be decoded.
+    BadRequest(String),
+    Overflow,
+    LineTooLong,
+    InvalidUtf8,
+    RateLimited,
+    /// The server is full, sent before closing the connection.
+    Busy,
+}
+
+impl CommandError {
+    /// Machine-readable part of the reply.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandError::UnknownCommand(_) => "UNKNOWN_COMMAND",
+            CommandError::MissingArgument(_) => "MISSING_ARGUMENT",
+            CommandError::InvalidArgument(_) => "INVALID_ARGUMENT",
+            CommandError::BadRequest(_) => "BAD_REQUEST",
+            CommandError::Overflow => "OVERFLOW",
+            CommandError::LineTooLong => "LINE_TOO_LONG",
+            CommandError::InvalidUtf8 => "INVALID_UTF8",
+            CommandError::RateLimited => "RATE_LIMITED",
+            CommandError::Busy => "BUSY",
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERR {} ", self.code())?;
+        match self {
+            CommandError::UnknownCommand(name) => write!(f, "unknown command '{name}', try HELP"),
+            CommandError::MissingArgument(name) => write!(f, "missing argument <{name}>"),
+            CommandError::InvalidArgument(reason) | CommandError::BadRequest(reason) => {
+                write!(f, "{reason}")
+            }
+            CommandError::Overflow => write!(f, "result doesn't fit in 64 bits"),
+            CommandError::LineTooLong => write!(f, "line too long"),
+            CommandError::InvalidUtf8 => write!(f, "line is not valid UTF-8"),
+            CommandError::RateLimited => write!(f, "too many commands, slow down"),
+            CommandError::Busy => write!(f, "too many clients, try again later"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+pub trait Command: Send + Sync + 'static {
+    /// `args` is the rest of the line after the command name, trimmed.
+    fn run(&self, args: &str) -> Result<Reply, CommandError>;
+}
+
+impl<F> Command for F
+where
+    F: Fn(&str) -> Result<Reply, CommandError> + Send + Sync + 'static,
+{
+    fn run(&self, args: &str) -> Result<Reply, CommandError> {
+        self(args)
+    }
+}
+
+struct Entry {
+    usage: &'static str,
+    command: Box<dyn Command>,
+}
+
+/// Runs commands by name, case-insensitively. `HELP` is always there and lists the
+/// others.
+pub struct Dispatcher {
+    commands: BTreeMap<String, Entry>,
+}
+
+impl Default for Dispatcher {
+    /// `PING`, `ECHO`, `DOUBLE` and `QUIT`.
+    fn default() -> Self {
+        Self::new()
+            .command("PING", "PING", |_: &str| {
+                Ok(Reply::Line("PONG".to_string()))
+            })
+            .command("ECHO", "ECHO <text>", |args: &str| {
+                Ok(Reply::Line(args.to_string()))
+            })
+            .command("DOUBLE", "DOUBLE <integer>", double)
+            .command("QUIT", "QUIT", |_: &str| {
+                Ok(Reply::Close("BYE".to_string()))
+            })
+    }
+}
+
+impl Dispatcher {
+    /// A dispatcher with only `HELP`.
+    pub fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a command, replacing any other with the same name.
+    pub fn command<C: Command>(mut self, name: &str, usage: &'static str, command: C) -> Self {
+        self.commands.insert(
+            name.to_ascii_uppercase(),
+            Entry {
+                usage,
+                command: Box::new(command),
+            },
+        );
+        self
+    }
+
+    pub fn dispatch(&self, line: &str) -> Result<Reply, CommandError> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let name = name.to_ascii_uppercase();
+
+        if name == "HELP" {
+            return Ok(Reply::Lines(self.help()));
+        }
+        match self.commands.get(&name) {
+            Some(entry) => entry.command.run(args.trim()),
+            None => Err(CommandError::UnknownCommand(name)),
+        }
+    }
+
+    fn help(&self) -> Vec<String> {
+        let mut usages = self
+            .commands
+            .values()
+            .map(|entry| entry.usage.to_string())
+            .collect::<Vec<_>>();
+        usages.push("HELP".to_string());
+        usages
+    }
+}
+
+fn double(args: &str) -> Result<Reply, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::MissingArgument("integer"));
+    }
+    let value = args
+        .parse::<i64>()
+        .map_err(|_| CommandError::InvalidArgument(format!("'{args}' is not an integer")))?;
+    let doubled = value.checked_mul(2).ok_or(CommandError::Overflow)?;
+    Ok(Reply::Line(doubled.to_string()))
+}
+
+#[test]
+fn builtin_commands() {
+    let dispatcher = Dispatcher::default();
+    let line = |s: &str| Ok(Reply::Line(s.to_string()));
+
+    assert_eq!(dispatcher.dispatch("PING"), line("PONG"));
+    assert_eq!(dispatcher.dispatch("ping"), line("PONG"));
+    assert_eq!(
+        dispatcher.dispatch("ECHO hello  world "),
+        line("hello  world")
+    );
+    assert_eq!(dispatcher.dispatch("DOUBLE 21"), line("42"));
+    assert_eq!(dispatcher.dispatch("DOUBLE -4"), line("-8"));
+    assert_eq!(
+        dispatcher.dispatch("QUIT"),
+        Ok(Reply::Close("BYE".to_string()))
+    );
+
+    let Ok(Reply::Lines(help)) = dispatcher.dispatch("HELP") else {
+        panic!("HELP replies with lines");
+    };
+    assert_eq!(
+        help,
+        ["DOUBLE <integer>", "ECHO <text>", "PING", "QUIT", "HELP"]
+    );
+}
+
+#[test]
+fn command_errors() {
+    let dispatcher = Dispatcher::default();
+    let error = |line: &str| dispatcher.dispatch(line).unwrap_err().to_string();
+
+    assert_eq!(
+        error("NOPE 1"),
+        "ERR UNKNOWN_COMMAND unknown command 'NOPE', try HELP"
+    );
+    assert_eq!(
+        error("DOUBLE"),
+        "ERR MISSING_ARGUMENT missing argument <integer>"
+    );
+    assert_eq!(
+        error("DOUBLE x"),
+        "ERR INVALID_ARGUMENT 'x' is not an integer"
+    );
+    assert_eq!(
+        error("DOUBLE 9223372036854775807"),
+        "ERR OVERFLOW result doesn't fit in 64 bits"
+    );
+}
+
+#[test]
+fn custom_commands() {
+    // New commands don't need anything else
+    let dispatcher = Dispatcher::new().command("UPPER", "UPPER <text>", |args: &str| {
+        Ok(Reply::Line(args.to_uppercase()))
+    });
+    assert_eq!(
+        dispatcher.dispatch("upper abc"),
+        Ok(Reply::Line("ABC".to_string()))
+    );
+    assert!(dispatcher.dispatch("PING").is_err());
+}
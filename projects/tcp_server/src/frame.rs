@@ -0,0 +1,127 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+/// Largest frame accepted by default, in bytes, not counting the length prefix.
+pub const DEFAULT_MAX_FRAME: u32 = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    /// Only the length was read, the payload is still in the stream, see
+    /// `skip_payload`.
+    TooLarge(u32),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "i/o error: {e}"),
+            FrameError::TooLarge(len) => write!(f, "frame of {len} bytes is too large"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+/// The next frame's payload, `None` if the stream ends cleanly before it. Each frame
+/// is its length as a big-endian `u32`, then that many bytes.
+pub fn read_frame<R: Read>(reader: &mut R, max_len: u32) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut header = [0; 4];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let len = u32::from_be_bytes(header);
+    if len > max_len {
+        return Err(FrameError::TooLarge(len));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Reads past the payload of a frame `read_frame` found too large, so the next frame
+/// can be read. Skipped rather than buffered.
+pub fn skip_payload<R: Read>(reader: &mut R, len: u32) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(u64::from(len)), &mut io::sink())?;
+    if skipped < u64::from(len) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// In a single write, prefix included.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+#[cfg(test)]
+fn three_frames() -> Vec<u8> {
+    let mut stream = Vec::new();
+    write_frame(&mut stream, b"hello").unwrap();
+    write_frame(&mut stream, b"").unwrap();
+    write_frame(&mut stream, &[7; 300]).unwrap();
+    stream
+}
+
+#[test]
+fn length_prefixed() {
+    let stream = three_frames();
+    assert_eq!(&stream[..9], b"\0\0\0\x05hello");
+
+    let mut reader = &stream[..];
+    let mut next = || read_frame(&mut reader, 300).map_err(|e| e.to_string());
+    assert_eq!(next(), Ok(Some(b"hello".to_vec())));
+    assert_eq!(next(), Ok(Some(Vec::new())));
+    assert_eq!(next(), Ok(Some(vec![7; 300])));
+    assert_eq!(next(), Ok(None));
+}
+
+#[test]
+fn too_large_frames_can_be_skipped() {
+    let mut stream = three_frames();
+    write_frame(&mut stream, b"after").unwrap();
+
+    let mut reader = &stream[13..];
+    let error = read_frame(&mut reader, 299).unwrap_err();
+    assert_eq!(error.to_string(), "frame of 300 bytes is too large");
+    assert!(matches!(error, FrameError::TooLarge(300)));
+    skip_payload(&mut reader, 300).unwrap();
+    assert_eq!(
+        read_frame(&mut reader, 299).unwrap(),
+        Some(b"after".to_vec())
+    );
+
+    let error = skip_payload(&mut &stream[17..20], 300).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn truncated_frames() {
+    let stream = three_frames();
+    // Ending inside a frame isn't a clean end
+    for truncated in [&stream[..2], &stream[..7]] {
+        let error = read_frame(&mut &truncated[..], 300).unwrap_err();
+        assert!(matches!(error, FrameError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::{Duration, Instant};
+
+use crate::{resp::Value, store::Store};
+
+/// Runs a decoded request, which should be an array of bulk strings. Blank inline
+/// requests get no reply at all.
+pub fn handle(store: &Store, request: Value) -> Option<Value> {
+    let Value::Array(items) = request else {
+        return Some(Value::error("ERR Protocol error: expected an array"));
+    };
+    if items.is_empty() {
+        return None;
+    }
+
+    let args = items
+        .into_iter()
+        .map(|item| match item {
+            Value::Bulk(bytes) => Some(bytes),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    Some(match args {
+        Some(args) => execute(store, &args),
+        None => Value::error("ERR Protocol error: expected bulk strings"),
+    })
+}
+
+/// Runs one command, `args[0]` being its name.
+pub fn execute(store: &Store, args: &[Vec<u8>]) -> Value {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let args = &args[1..];
+
+    let arity_ok = match name.as_str() {
+        "ping" => args.len() <= 1,
+        "get" | "incr" | "keys" => args.len() == 1,
+        "set" => args.len() >= 2,
+        "del" => !args.is_empty(),
+        "expire" => args.len() == 2,
+        _ => {
+            let first = args.first().map(|arg| String::from_utf8_lossy(arg));
+            return Value::error(format!(
+                "ERR unknown command '{name}', with args beginning with: {}",
+                first.map_or(String::new(), |arg| format!("'{arg}'"))
+            ));
+        }
+    };
+    if !arity_ok {
+        return Value::error(format!(
+            "ERR wrong number of arguments for '{name}' command"
+        ));
+    }
+
+    match name.as_str() {
+        "ping" => match args.first() {
+            Some(message) => Value::bulk(message.clone()),
+            None => Value::Simple("PONG".to_string()),
+        },
+        "get" => store.get(&args[0]).map_or(Value::Null, Value::Bulk),
+        "set" => set(store, args),
+        "del" => Value::Integer(args.iter().filter(|key| store.del(key)).count() as i64),
+        "incr" => match store.incr(&args[0]) {
+            Ok(value) => Value::Integer(value),
+            Err(e) => Value::error(format!("ERR {e}")),
+        },
+        "expire" => match integer(&args[1]) {
+            // Zero or less expires the key right away
+            Some(secs) => match deadline(secs.max(0), 1000) {
+                Some(at) => Value::Integer(store.expire(&args[0], at) as i64),
+                None => Value::error("ERR invalid expire time in 'expire' command"),
+            },
+            None => not_an_integer(),
+        },
+        "keys" => Value::Array(store.keys(&args[0]).into_iter().map(Value::Bulk).collect()),
+        _ => unreachable!("checked above"),
+    }
+}
+
+/// `SET key value [EX seconds | PX milliseconds]`
+fn set(store: &Store, args: &[Vec<u8>]) -> Value {
+    let mut expires_at = None;
+    let mut options = args[2..].iter();
+
+    while let Some(option) = options.next() {
+        let unit_ms = match option.to_ascii_uppercase().as_slice() {
+            b"EX" => 1000,
+            b"PX" => 1,
+            _ => return Value::error("ERR syntax error"),
+        };
+        if expires_at.is_some() {
+            return Value::error("ERR syntax error");
+        }
+        let Some(amount) = options.next() else {
+            return Value::error("ERR syntax error");
+        };
+        let Some(amount) = integer(amount) else {
+            return not_an_integer();
+        };
+        if amount <= 0 {
+            return Value::error("ERR invalid expire time in 'set' command");
+        }
+        match deadline(amount, unit_ms) {
+            Some(at) => expires_at = Some(at),
+            None => return Value::error("ERR invalid expire time in 'set' command"),
+        }
+    }
+
+    store.set(args[0].clone(), args[1].clone(), expires_at);
+    Value::ok()
+}
+
+/// `amount` units of `unit_ms` from now, `None` if that can't be represented.
+fn deadline(amount: i64, unit_ms: u64) -> Option<Instant> {
+    let ms = u64::try_from(amount).ok()?.checked_mul(unit_ms)?;
+    Instant::now().checked_add(Duration::from_millis(ms))
+}
+
+fn integer(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+fn not_an_integer() -> Value {
+    Value::error("ERR value is not an integer or out of range")
+}
+
+#[cfg(test)]
+fn run(store: &Store, args: &[&str]) -> Value {
+    let args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+    execute(store, &args)
+}
+
+#[test]
+fn set_overwrites() {
+    let store = Store::new();
+    assert_eq!(run(&store, &["GET", "k"]), Value::Null);
+    assert_eq!(run(&store, &["SET", "k", "1", "EX", "60"]), Value::ok());
+    assert_eq!(run(&store, &["set", "k", "2"]), Value::ok());
+    assert_eq!(run(&store, &["get", "k"]), Value::bulk("2"));
+    assert_eq!(run(&store, &["INCR", "k"]), Value::Integer(3));
+    assert_eq!(
+        run(&store, &["KEYS", "*"]),
+        Value::Array(vec![Value::bulk("k")])
+    );
+}
+
+#[test]
+fn del_counts_removed_keys() {
+    let store = Store::new();
+    run(&store, &["SET", "a", "1"]);
+    run(&store, &["SET", "b", "2"]);
+    assert_eq!(
+        run(&store, &["DEL", "a", "b", "missing"]),
+        Value::Integer(2)
+    );
+    assert_eq!(run(&store, &["DEL", "a"]), Value::Integer(0));
+    assert_eq!(run(&store, &["GET", "b"]), Value::Null);
+}
+
+#[test]
+fn keys_expire() {
+    let store = Store::new();
+    assert_eq!(run(&store, &["SET", "px", "1", "PX", "20"]), Value::ok());
+    assert_eq!(run(&store, &["SET", "kept", "1"]), Value::ok());
+    assert_eq!(run(&store, &["SET", "now", "1"]), Value::ok());
+    assert_eq!(run(&store, &["EXPIRE", "now", "0"]), Value::Integer(1));
+    assert_eq!(run(&store, &["EXPIRE", "missing", "10"]), Value::Integer(0));
+    assert_eq!(run(&store, &["GET", "now"]), Value::Null);
+    assert_eq!(run(&store, &["GET", "px"]), Value::bulk("1"));
+
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(run(&store, &["GET", "px"]), Value::Null);
+    assert_eq!(
+        run(&store, &["KEYS", "*"]),
+        Value::Array(vec![Value::bulk("kept")])
+    );
+}
+
+#[test]
+fn bad_commands() {
+    let store = Store::new();
+    for (args, error) in [
+        (
+            &["nope", "a"][..],
+            "ERR unknown command 'nope', with args beginning with: 'a'",
+        ),
+        (&["GET"], "ERR wrong number of arguments for 'get' command"),
+        (&["SET", "k", "v", "EX"], "ERR syntax error"),
+        (&["SET", "k", "v", "EX", "1", "PX", "1"], "ERR syntax error"),
+        (&["SET", "k", "v", "NX"], "ERR syntax error"),
+        (
+            &["SET", "k", "v", "EX", "0"],
+            "ERR invalid expire time in 'set' command",
+        ),
+        (
+            &["SET", "k", "v", "PX", "x"],
+            "ERR value is not an integer or out of range",
+        ),
+        (
+            &["EXPIRE", "k", "1.5"],
+            "ERR value is not an integer or out of range",
+        ),
+    ] {
+        assert_eq!(run(&store, args), Value::error(error), "{args:?}");
+    }
+    assert_eq!(run(&store, &["KEYS", "*"]), Value::Array(vec![]));
+}
+
+#[test]
+fn handles_requests() {
+    let store = Store::new();
+    let request = Value::Array(vec![Value::bulk("PING"), Value::bulk("hi")]);
+    assert_eq!(handle(&store, request), Some(Value::bulk("hi")));
+    assert_eq!(handle(&store, Value::Array(vec![])), None);
+    assert_eq!(
+        handle(&store, Value::bulk("PING")),
+        Some(Value::error("ERR Protocol error: expected an array"))
+    );
+    assert_eq!(
+        handle(&store, Value::Array(vec![Value::Integer(1)])),
+        Some(Value::error("ERR Protocol error: expected bulk strings"))
+    );
+}
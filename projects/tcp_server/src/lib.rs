@@ -0,0 +1,13 @@
+pub mod binary;
+pub mod chat;
+pub mod client;
+pub mod codec;
+pub mod command;
+pub mod frame;
+pub mod kv;
+pub mod limit;
+pub mod message;
+pub mod resp;
+pub mod server;
+pub mod store;
+pub mod tokio_server;
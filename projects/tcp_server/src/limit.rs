@@ -0,0 +1,94 @@
+use std::time::Instant;
+
+/// How many messages a client may send: `burst` at once, then `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_second: 100.0,
+            burst: 200,
+        }
+    }
+}
+
+/// A token bucket, one per client.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: Option<RateLimit>,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// With no limit every message is allowed.
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            tokens: limit.map_or(0.0, |limit| f64::from(limit.burst)),
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Whether another message is allowed now, counting it if so.
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(f64::from(limit.burst));
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+fn two_per_second() -> (RateLimiter, impl Fn(u64) -> Instant) {
+    let limiter = RateLimiter::new(Some(RateLimit {
+        per_second: 2.0,
+        burst: 3,
+    }));
+    let start = limiter.refilled;
+    (limiter, move |ms| {
+        start + std::time::Duration::from_millis(ms)
+    })
+}
+
+#[test]
+fn burst_then_steady_rate() {
+    let (mut limiter, at) = two_per_second();
+    assert!((0..3).all(|_| limiter.allow_at(at(0))));
+    assert!(!limiter.allow_at(at(0)));
+    assert!(!limiter.allow_at(at(400)));
+    assert!(limiter.allow_at(at(500)));
+    assert!(!limiter.allow_at(at(500)));
+}
+
+#[test]
+fn refill_stops_at_burst() {
+    let (mut limiter, at) = two_per_second();
+    assert!((0..3).all(|_| limiter.allow_at(at(0))));
+    // Idle time only refills up to the burst
+    assert!((0..3).all(|_| limiter.allow_at(at(10_000))));
+    assert!(!limiter.allow_at(at(10_000)));
+}
+
+#[test]
+fn unlimited() {
+    let mut unlimited = RateLimiter::new(None);
+    assert!((0..10_000).all(|_| unlimited.allow()));
+}
@@ -1,54 +1,77 @@
+use std::env;
 use std::error;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
+use std::sync::Arc;
+use std::time::Duration;
 
-fn main() -> Result<(), Box<dyn error::Error>> {
-    let listener = TcpListener::bind("localhost:8080")?;
-    println!("Server listening on port 8080");
+use tcp_server::{
+    binary::BinaryHandler,
+    chat::ChatServer,
+    command::Dispatcher,
+    server::{Config, Server},
+    store::Store,
+    tokio_server::TokioServer,
+};
 
-    for stream in listener.incoming() {
-        let client_stream = stream?;
-        println!("Client Connected");
+/// How often keys nobody reads again are checked for expiry.
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
 
-        thread::spawn(move || {
-            if let Err(e) = handle_client(client_stream) {
-                eprintln!("Error handling client: {}", e);
-            }
-        });
+/// `tcp_server [resp|lines|chat|binary] [--backend threads|tokio]`, `resp` being a
+/// Redis compatible key-value server, `lines` the plain text commands, `chat` chat
+/// rooms and `binary` length-prefixed requests. The tokio backend serves `lines` only.
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let mut mode = "resp".to_string();
+    let mut backend = "threads".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--backend" => backend = args.next().ok_or("--backend needs a value")?,
+            _ => mode = arg,
+        }
     }
-    Ok(())
-}
 
-fn handle_client(mut stream: TcpStream) -> Result<(), Box<dyn error::Error>> {
-    let mut buffer = [0; 1024];
-    loop {
-        match stream.read(&mut buffer) {
-            Ok(0) => {
-                eprintln!("Client Disconnected");
-                break;
-            }
-            Ok(n) => {
-                let mut buf = &buffer[0..n];
-                let mut data = String::new();
-                buf.read_to_string(&mut data)?;
-                let data = data.trim();
-                println!("Received: {data}");
+    let port = match mode.as_str() {
+        "resp" => 6379,
+        "lines" | "chat" => 8080,
+        "binary" => 7070,
+        _ => {
+            return Err(
+                format!("unknown mode '{mode}', expected resp, lines, chat or binary").into(),
+            )
+        }
+    };
+    match (backend.as_str(), mode.as_str()) {
+        ("threads", _) => {}
+        ("tokio", "lines") => return run_tokio(port),
+        ("tokio", _) => return Err(format!("the tokio backend doesn't serve {mode}").into()),
+        _ => return Err(format!("unknown backend '{backend}', expected threads or tokio").into()),
+    }
+
+    let server = Server::bind(("localhost", port), Config::default())?;
+    println!("Server listening on port {port} ({mode})");
 
-                if let Ok(mut value) = data.parse::<i32>() {
-                    value *= 2;
-                    let str_value = value.to_string() + "\n";
-                    stream.write_all(str_value.as_bytes())?;
-                } else {
-                    // Echo the buffer
-                    stream.write_all(&buffer[0..n])?;
-                }
-            }
-            Err(e) => {
-                eprintln!("Failed to read from stream: {}", e);
-                break;
-            }
+    match mode.as_str() {
+        "resp" => {
+            let store = Arc::new(Store::new());
+            store.spawn_expirer(EXPIRE_INTERVAL);
+            server.run(store);
+        }
+        "lines" => server.run(Arc::new(Dispatcher::default())),
+        "binary" => {
+            let store = Arc::new(Store::new());
+            store.spawn_expirer(EXPIRE_INTERVAL);
+            server.run(Arc::new(BinaryHandler::new(store)));
         }
+        _ => server.run(Arc::new(ChatServer::new())),
     }
     Ok(())
 }
+
+fn run_tokio(port: u16) -> Result<(), Box<dyn error::Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let server = TokioServer::bind(("localhost", port), Config::default()).await?;
+        println!("Server listening on port {port} (lines, tokio)");
+        server.run(Arc::new(Dispatcher::default())).await;
+        Ok(())
+    })
+}
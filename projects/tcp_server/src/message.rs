@@ -0,0 +1,241 @@
+use std::fmt;
+
+/// What a binary client asks for, sent as one frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Ping,
+    Echo(Vec<u8>),
+    Double(i64),
+    Get(Vec<u8>),
+    Set(Vec<u8>, Vec<u8>),
+    Del(Vec<u8>),
+}
+
+/// The reply to a `Request`, sent as one frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Pong,
+    Data(Vec<u8>),
+    Integer(i64),
+    /// `None` for a missing key.
+    Value(Option<Vec<u8>>),
+    Ok,
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnknownTag(u8),
+    TrailingBytes,
+    InvalidUtf8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "message ends too early"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown message tag {tag:#04x}"),
+            DecodeError::TrailingBytes => write!(f, "bytes left after the message"),
+            DecodeError::InvalidUtf8 => write!(f, "text is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// A tag byte, then the fields: integers as 8 big-endian bytes, byte strings as their
+// length in 4 big-endian bytes then the bytes, options as a 0 or 1 byte then the value.
+const PING: u8 = 0x01;
+const ECHO: u8 = 0x02;
+const DOUBLE: u8 = 0x03;
+const GET: u8 = 0x04;
+const SET: u8 = 0x05;
+const DEL: u8 = 0x06;
+
+const PONG: u8 = 0x81;
+const DATA: u8 = 0x82;
+const INTEGER: u8 = 0x83;
+const VALUE: u8 = 0x84;
+const OK: u8 = 0x85;
+const ERROR: u8 = 0xff;
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Encoder(Vec::new());
+        match self {
+            Request::Ping => out.tag(PING),
+            Request::Echo(data) => out.tag(ECHO).bytes(data),
+            Request::Double(n) => out.tag(DOUBLE).integer(*n),
+            Request::Get(key) => out.tag(GET).bytes(key),
+            Request::Set(key, value) => out.tag(SET).bytes(key).bytes(value),
+            Request::Del(key) => out.tag(DEL).bytes(key),
+        };
+        out.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut input = Decoder(bytes);
+        let request = match input.tag()? {
+            PING => Request::Ping,
+            ECHO => Request::Echo(input.bytes()?),
+            DOUBLE => Request::Double(input.integer()?),
+            GET => Request::Get(input.bytes()?),
+            SET => Request::Set(input.bytes()?, input.bytes()?),
+            DEL => Request::Del(input.bytes()?),
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+        input.end()?;
+        Ok(request)
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Encoder(Vec::new());
+        match self {
+            Response::Pong => out.tag(PONG),
+            Response::Data(data) => out.tag(DATA).bytes(data),
+            Response::Integer(n) => out.tag(INTEGER).integer(*n),
+            Response::Value(None) => out.tag(VALUE).tag(0),
+            Response::Value(Some(value)) => out.tag(VALUE).tag(1).bytes(value),
+            Response::Ok => out.tag(OK),
+            Response::Error(message) => out.tag(ERROR).bytes(message.as_bytes()),
+        };
+        out.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut input = Decoder(bytes);
+        let response = match input.tag()? {
+            PONG => Response::Pong,
+            DATA => Response::Data(input.bytes()?),
+            INTEGER => Response::Integer(input.integer()?),
+            VALUE => match input.tag()? {
+                0 => Response::Value(None),
+                1 => Response::Value(Some(input.bytes()?)),
+                flag => return Err(DecodeError::UnknownTag(flag)),
+            },
+            OK => Response::Ok,
+            ERROR => Response::Error(
+                String::from_utf8(input.bytes()?).map_err(|_| DecodeError::InvalidUtf8)?,
+            ),
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+        input.end()?;
+        Ok(response)
+    }
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn tag(&mut self, tag: u8) -> &mut Self {
+        self.0.push(tag);
+        self
+    }
+
+    fn integer(&mut self, n: i64) -> &mut Self {
+        self.0.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    /// Frames are at most `u32::MAX` bytes, so a length always fits.
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.0.extend_from_slice(bytes);
+        self
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        if self.0.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn tag(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn integer(&mut self) -> Result<i64, DecodeError> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap());
+        Ok(self.take(len as usize)?.to_vec())
+    }
+
+    fn end(&self) -> Result<(), DecodeError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+}
+
+#[test]
+fn encoding_roundtrip() {
+    for request in [
+        Request::Ping,
+        Request::Echo(b"\0binary\xff".to_vec()),
+        Request::Double(-21),
+        Request::Get(b"key".to_vec()),
+        Request::Set(b"key".to_vec(), Vec::new()),
+        Request::Del(b"key".to_vec()),
+    ] {
+        assert_eq!(Request::decode(&request.encode()), Ok(request));
+    }
+    for response in [
+        Response::Pong,
+        Response::Data(b"data".to_vec()),
+        Response::Integer(i64::MIN),
+        Response::Value(None),
+        Response::Value(Some(b"value".to_vec())),
+        Response::Ok,
+        Response::Error("nope".to_string()),
+    ] {
+        assert_eq!(Response::decode(&response.encode()), Ok(response));
+    }
+}
+
+#[test]
+fn wire_format() {
+    assert_eq!(
+        Request::Set(b"k".to_vec(), b"v".to_vec()).encode(),
+        b"\x05\0\0\0\x01k\0\0\0\x01v"
+    );
+    assert_eq!(Request::Double(1).encode(), b"\x03\0\0\0\0\0\0\0\x01");
+    assert_eq!(Response::Value(None).encode(), b"\x84\0");
+}
+
+#[test]
+fn malformed_messages() {
+    assert_eq!(Request::decode(b""), Err(DecodeError::UnexpectedEnd));
+    assert_eq!(
+        Request::decode(b"\x03\0\0"),
+        Err(DecodeError::UnexpectedEnd)
+    );
+    assert_eq!(
+        Request::decode(b"\x02\xff\xff\xff\xff"),
+        Err(DecodeError::UnexpectedEnd)
+    );
+    assert_eq!(Request::decode(b"\x42"), Err(DecodeError::UnknownTag(0x42)));
+    assert_eq!(
+        Request::decode(b"\x01\x01"),
+        Err(DecodeError::TrailingBytes)
+    );
+    assert_eq!(
+        Response::decode(b"\xff\0\0\0\x01\xff"),
+        Err(DecodeError::InvalidUtf8)
+    );
+}
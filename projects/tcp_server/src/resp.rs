@@ -0,0 +1,394 @@
+use std::fmt;
+
+/// Largest bulk string or inline request accepted by default, in bytes.
+pub const DEFAULT_MAX_BULK: usize = 16 * 1024 * 1024;
+
+/// Largest request accepted by default, all its bulk strings included, in bytes.
+pub const DEFAULT_MAX_REQUEST: usize = 4 * DEFAULT_MAX_BULK;
+
+/// Arrays nested deeper than this are refused, so a request can't overflow the stack.
+const MAX_DEPTH: usize = 8;
+
+/// A RESP2 value, as sent by clients and servers alike.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    /// The null bulk string, `$-1`. A null array decodes to it too.
+    Null,
+}
+
+impl Value {
+    pub fn ok() -> Self {
+        Value::Simple("OK".to_string())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Value::Error(message.into())
+    }
+
+    pub fn bulk(bytes: impl Into<Vec<u8>>) -> Self {
+        Value::Bulk(bytes.into())
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Simple(s) => push_line(out, b'+', s.as_bytes()),
+            Value::Error(s) => push_line(out, b'-', s.as_bytes()),
+            Value::Integer(n) => push_line(out, b':', n.to_string().as_bytes()),
+            Value::Bulk(bytes) => {
+                push_line(out, b'$', bytes.len().to_string().as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Value::Array(items) => {
+                push_line(out, b'*', items.len().to_string().as_bytes());
+                items.iter().for_each(|item| item.encode(out));
+            }
+            Value::Null => out.extend_from_slice(b"$-1\r\n"),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+fn push_line(out: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    out.push(kind);
+    // Simple strings and errors can't span lines
+    out.extend(
+        content
+            .iter()
+            .map(|&b| if b == b'\r' || b == b'\n' { b' ' } else { b }),
+    );
+    out.extend_from_slice(b"\r\n");
+}
+
+/// The peer broke the protocol, there's no telling where the next value starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RespError {
+    InvalidLength,
+    InvalidInteger,
+    MissingCrlf,
+    TooLarge,
+    TooDeep,
+    UnexpectedType(u8),
+}
+
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERR Protocol error: ")?;
+        match self {
+            RespError::InvalidLength => write!(f, "invalid length"),
+            RespError::InvalidInteger => write!(f, "invalid integer"),
+            RespError::MissingCrlf => write!(f, "expected CRLF after bulk string"),
+            RespError::TooLarge => write!(f, "request too large"),
+            RespError::TooDeep => write!(f, "arrays nested too deeply"),
+            RespError::UnexpectedType(b) => {
+                write!(f, "unexpected '{}'", char::from(*b).escape_default())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RespError {}
+
+/// Decodes the value at the start of `buf`, with the number of bytes it took. `None`
+/// means `buf` doesn't hold a whole value yet.
+///
+/// A line that doesn't start with a type byte is an inline command, like
+/// `SET key value`, and decodes to an array of its words. A blank one decodes to an
+/// empty array.
+pub fn decode(buf: &[u8], max_bulk: usize) -> Result<Option<(Value, usize)>, RespError> {
+    let mut decoder = Decoder::new(max_bulk, usize::MAX);
+    Ok(match decoder.decode(buf)? {
+        (len, Some(value)) => Some((value, len)),
+        (_, None) => None,
+    })
+}
+
+/// Decodes values as their bytes arrive, keeping what it made of an incomplete one
+/// so the bytes already read aren't decoded again.
+///
+/// After an error there's no telling where the next value starts, the decoder is of
+/// no more use.
+#[derive(Debug)]
+pub struct Decoder {
+    max_bulk: usize,
+    max_request: usize,
+    /// Arrays being filled, outermost first, with how many items each still needs.
+    open: Vec<(Vec<Value>, usize)>,
+    /// Bytes of the current value already decoded into `open`.
+    decoded: usize,
+    /// Bytes at the start of the input already searched for a newline.
+    scanned: usize,
+}
+
+/// A value, or the start of an array whose items follow.
+enum Item {
+    Value(Value),
+    Array(usize),
+}
+
+impl Decoder {
+    /// Bulk strings and inline requests over `max_bulk` bytes are refused, and so
+    /// are values over `max_request` bytes in all, or arrays claiming more items
+    /// than that.
+    pub fn new(max_bulk: usize, max_request: usize) -> Self {
+        Self {
+            max_bulk,
+            max_request,
+            open: Vec::new(),
+            decoded: 0,
+            scanned: 0,
+        }
+    }
+
+    /// Decodes what it can of the value at the start of `buf`. Returns how many bytes
+    /// were used up, for the caller to drop before passing the rest and whatever
+    /// arrived since, and the value once it's whole.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<(usize, Option<Value>), RespError> {
+        let mut pos = 0;
+        loop {
+            let rest = &buf[pos..];
+            let Some((item, len)) = self.item(rest)? else {
+                if self.decoded + rest.len() > self.max_request {
+                    return Err(RespError::TooLarge);
+                }
+                return Ok((pos, None));
+            };
+            pos += len;
+            self.decoded += len;
+            self.scanned = 0;
+            if self.decoded > self.max_request {
+                return Err(RespError::TooLarge);
+            }
+
+            let mut value = match item {
+                Item::Array(_) if self.open.len() == MAX_DEPTH => return Err(RespError::TooDeep),
+                Item::Array(0) => Value::Array(Vec::new()),
+                Item::Array(len) => {
+                    // The length is only a claim, allocate as the items turn up
+                    self.open.push((Vec::with_capacity(len.min(64)), len));
+                    continue;
+                }
+                Item::Value(value) => value,
+            };
+            // Into the arrays it completes, the value is whole once none is left open
+            loop {
+                let Some((items, missing)) = self.open.last_mut() else {
+                    self.decoded = 0;
+                    return Ok((pos, Some(value)));
+                };
+                items.push(value);
+                *missing -= 1;
+                if *missing > 0 {
+                    break;
+                }
+                value = Value::Array(self.open.pop().unwrap().0);
+            }
+        }
+    }
+
+    /// The item at the start of `rest`, with the number of bytes it took.
+    fn item(&mut self, rest: &[u8]) -> Result<Option<(Item, usize)>, RespError> {
+        let Some(end) = self.line_end(rest)? else {
+            return Ok(None);
+        };
+        let line = &rest[..end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut len = end + 1;
+        let Some((&kind, rest_of_line)) = line.split_first() else {
+            return self.inline(line, len);
+        };
+
+        let item = match kind {
+            b'+' => Item::Value(Value::Simple(
+                String::from_utf8_lossy(rest_of_line).into_owned(),
+            )),
+            b'-' => Item::Value(Value::Error(
+                String::from_utf8_lossy(rest_of_line).into_owned(),
+            )),
+            b':' => Item::Value(Value::Integer(integer(rest_of_line)?)),
+            b'$' => match length(rest_of_line, self.max_bulk)? {
+                None => Item::Value(Value::Null),
+                Some(bulk) => {
+                    let Some(bytes) = rest.get(len..len + bulk + 2) else {
+                        return Ok(None);
+                    };
+                    if !bytes.ends_with(b"\r\n") {
+                        return Err(RespError::MissingCrlf);
+                    }
+                    len += bulk + 2;
+                    Item::Value(Value::Bulk(bytes[..bulk].to_vec()))
+                }
+            },
+            b'*' => match length(rest_of_line, self.max_request)? {
+                None => Item::Value(Value::Null),
+                Some(items) => Item::Array(items),
+            },
+            _ => return self.inline(line, len),
+        };
+        Ok(Some((item, len)))
+    }
+
+    /// Where the line at the start of `rest` ends. The bytes searched are remembered,
+    /// they aren't searched again when more arrive.
+    fn line_end(&mut self, rest: &[u8]) -> Result<Option<usize>, RespError> {
+        match rest[self.scanned..].iter().position(|&b| b == b'\n') {
+            Some(i) => Ok(Some(self.scanned + i)),
+            None if rest.len() > self.max_bulk => Err(RespError::TooLarge),
+            None => {
+                self.scanned = rest.len();
+                Ok(None)
+            }
+        }
+    }
+
+    fn inline(&self, line: &[u8], len: usize) -> Result<Option<(Item, usize)>, RespError> {
+        if !self.open.is_empty() {
+            return Err(RespError::UnexpectedType(
+                line.first().copied().unwrap_or(b'\n'),
+            ));
+        }
+        let words = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(Value::bulk)
+            .collect();
+        Ok(Some((Item::Value(Value::Array(words)), len)))
+    }
+}
+
+/// A bulk string or array length, `None` for `-1`.
+fn length(digits: &[u8], max: usize) -> Result<Option<usize>, RespError> {
+    match integer(digits).map_err(|_| RespError::InvalidLength)? {
+        -1 => Ok(None),
+        len if len < 0 => Err(RespError::InvalidLength),
+        len if len as u64 > max as u64 => Err(RespError::TooLarge),
+        len => Ok(Some(len as usize)),
+    }
+}
+
+fn integer(digits: &[u8]) -> Result<i64, RespError> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(RespError::InvalidInteger)
+}
+
+#[cfg(test)]
+const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nv\r\nv!\r\n";
+
+#[cfg(test)]
+fn set_request() -> Value {
+    Value::Array(vec![
+        Value::bulk("SET"),
+        Value::bulk("k"),
+        Value::bulk("v\r\nv!"),
+    ])
+}
+
+#[test]
+fn roundtrip() {
+    let max = DEFAULT_MAX_BULK;
+    assert_eq!(decode(SET, max), Ok(Some((set_request(), SET.len()))));
+    assert_eq!(set_request().to_bytes(), SET);
+
+    for (bytes, value) in [
+        (&b"+OK\r\n"[..], Value::ok()),
+        (b"-ERR nope\r\n", Value::error("ERR nope")),
+        (b":-42\r\n", Value::Integer(-42)),
+        (b"$-1\r\n", Value::Null),
+        (b"$0\r\n\r\n", Value::bulk("")),
+        (b"*0\r\n", Value::Array(vec![])),
+    ] {
+        assert_eq!(decode(bytes, max), Ok(Some((value.clone(), bytes.len()))));
+        assert_eq!(value.to_bytes(), bytes);
+    }
+    assert_eq!(
+        Value::error("a\r\nb").to_bytes(),
+        b"-a  b\r\n",
+        "errors stay on one line"
+    );
+}
+
+#[test]
+fn partial_and_pipelined() {
+    let max = DEFAULT_MAX_BULK;
+    // Every prefix is incomplete, and whatever follows is left alone
+    for end in 0..SET.len() {
+        assert_eq!(decode(&SET[..end], max), Ok(None));
+    }
+    let mut pipelined = SET.to_vec();
+    pipelined.extend_from_slice(b"*1\r\n$4\r\nPING\r\n");
+    assert_eq!(decode(&pipelined, max).unwrap().unwrap().1, SET.len());
+
+    // Fed a byte at a time, with the bytes used up dropped as a server would
+    let mut decoder = Decoder::new(max, 64);
+    let mut buf = Vec::new();
+    let mut decoded = None;
+    for &byte in SET {
+        buf.push(byte);
+        let (len, value) = decoder.decode(&buf).unwrap();
+        buf.drain(..len);
+        decoded = value;
+    }
+    assert_eq!((decoded, buf.len()), (Some(set_request()), 0));
+}
+
+#[test]
+fn size_limits() {
+    assert_eq!(decode(b"$11\r\n", 10), Err(RespError::TooLarge));
+    assert_eq!(decode(&[b'x'; 11], 10), Err(RespError::TooLarge));
+    assert_eq!(
+        decode(&b"*1\r\n".repeat(9), DEFAULT_MAX_BULK),
+        Err(RespError::TooDeep)
+    );
+
+    // Bounded as a whole, however many reads it arrives in
+    assert_eq!(
+        Decoder::new(DEFAULT_MAX_BULK, 64).decode(b"*65\r\n"),
+        Err(RespError::TooLarge)
+    );
+    let mut decoder = Decoder::new(DEFAULT_MAX_BULK, 64);
+    let start = [&b"*2\r\n$40\r\n"[..], &[b'x'; 40], b"\r\n$40\r\n"].concat();
+    assert_eq!(decoder.decode(&start), Ok((51, None)));
+    assert_eq!(
+        decoder.decode(b"$40\r\nxxxxxxxxxx"),
+        Err(RespError::TooLarge)
+    );
+}
+
+#[test]
+fn inline_commands() {
+    let max = DEFAULT_MAX_BULK;
+    // As typed into a terminal
+    assert_eq!(
+        decode(b"get  key\r\n", max),
+        Ok(Some((
+            Value::Array(vec![Value::bulk("get"), Value::bulk("key")]),
+            10
+        )))
+    );
+    assert_eq!(decode(b"\r\n", max), Ok(Some((Value::Array(vec![]), 2))));
+}
+
+#[test]
+fn malformed_input() {
+    let max = DEFAULT_MAX_BULK;
+    assert_eq!(decode(b"$-2\r\n", max), Err(RespError::InvalidLength));
+    assert_eq!(decode(b"$x\r\n", max), Err(RespError::InvalidLength));
+    assert_eq!(decode(b":1.5\r\n", max), Err(RespError::InvalidInteger));
+    assert_eq!(decode(b"$1\r\nab\r\n", max), Err(RespError::MissingCrlf));
+    assert_eq!(
+        decode(b"*1\r\nPING\r\n", max),
+        Err(RespError::UnexpectedType(b'P'))
+    );
+}
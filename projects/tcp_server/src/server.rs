@@ -0,0 +1,580 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    codec::{CodecError, LineReader, DEFAULT_MAX_LINE},
+    command::{CommandError, Dispatcher, Reply},
+    frame::DEFAULT_MAX_FRAME,
+    kv,
+    limit::{RateLimit, RateLimiter},
+    resp::{Decoder, Value, DEFAULT_MAX_BULK, DEFAULT_MAX_REQUEST},
+    store::Store,
+};
+
+/// Pause after a failed accept, the cause (like running out of file descriptors)
+/// tends to stick around for a bit.
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Longest command line accepted, longer ones get an error reply.
+    pub max_line_len: usize,
+    /// Largest RESP bulk string or inline request accepted.
+    pub max_bulk_len: usize,
+    /// Largest RESP request accepted, in bytes, which also bounds how many items an
+    /// array may claim to have.
+    pub max_request_len: usize,
+    /// Largest binary frame accepted, larger ones are skipped with an error response.
+    pub max_frame_len: u32,
+    /// Clients served at once, the next ones are turned away.
+    pub max_clients: usize,
+    /// How long a client may go without sending anything before it's disconnected.
+    pub idle_timeout: Option<Duration>,
+    /// Messages a client may send, the extra ones get an error reply.
+    pub rate_limit: Option<RateLimit>,
+    /// Log each client connecting and disconnecting, errors are logged regardless.
+    pub log_clients: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_line_len: DEFAULT_MAX_LINE,
+            max_bulk_len: DEFAULT_MAX_BULK,
+            max_request_len: DEFAULT_MAX_REQUEST,
+            max_frame_len: DEFAULT_MAX_FRAME,
+            max_clients: 1024,
+            idle_timeout: Some(Duration::from_secs(300)),
+            rate_limit: Some(RateLimit::default()),
+            log_clients: true,
+        }
+    }
+}
+
+/// A protocol `Server` can serve.
+pub trait Handler: Send + Sync + 'static {
+    /// Serves one client until it's done.
+    fn handle(&self, stream: TcpStream, config: &Config) -> io::Result<()>;
+
+    /// Sent to clients turned away because the server is full.
+    fn busy_message(&self) -> Vec<u8>;
+}
+
+/// The plain text commands.
+impl Handler for Dispatcher {
+    fn handle(&self, stream: TcpStream, config: &Config) -> io::Result<()> {
+        handle_client(stream, self, config)
+    }
+
+    fn busy_message(&self) -> Vec<u8> {
+        format!("{}\n", CommandError::Busy).into_bytes()
+    }
+}
+
+/// The RESP key-value server.
+impl Handler for Store {
+    fn handle(&self, stream: TcpStream, config: &Config) -> io::Result<()> {
+        handle_resp_client(stream, self, config)
+    }
+
+    fn busy_message(&self) -> Vec<u8> {
+        Value::error("ERR max number of clients reached").to_bytes()
+    }
+}
+
+/// Accepts clients and serves each on its own thread, up to `Config::max_clients`.
+pub struct Server {
+    listener: TcpListener,
+    config: Config,
+    clients: Arc<AtomicUsize>,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, config: Config) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            config,
+            clients: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients forever. Failing to accept one is logged, not fatal.
+    pub fn run<H: Handler>(&self, handler: Arc<H>) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => self.serve(stream, addr, &handler),
+                Err(e) => {
+                    eprintln!("Failed to accept a client: {e}");
+                    thread::sleep(ACCEPT_BACKOFF);
+                }
+            }
+        }
+    }
+
+    fn serve<H: Handler>(&self, mut stream: TcpStream, addr: SocketAddr, handler: &Arc<H>) {
+        let connected = self.clients.fetch_add(1, Ordering::AcqRel);
+        // Decrements again when dropped, whichever way the client is done
+        let slot = ClientSlot(Arc::clone(&self.clients));
+        if connected >= self.config.max_clients {
+            eprintln!("Turned {addr} away, {connected} clients connected");
+            let _ = stream.write_all(&handler.busy_message());
+            let _ = stream.shutdown(Shutdown::Write);
+            return;
+        }
+        if self.config.log_clients {
+            println!("Client Connected");
+        }
+
+        let config = self.config;
+        let handler = Arc::clone(handler);
+        let spawned = thread::Builder::new().spawn(move || {
+            let _slot = slot;
+            let timeouts = stream
+                .set_read_timeout(config.idle_timeout)
+                .and_then(|()| stream.set_write_timeout(config.idle_timeout));
+            let result = timeouts.and_then(|()| handler.handle(stream, &config));
+            log_disconnect(&config, addr, result);
+        });
+        if let Err(e) = spawned {
+            eprintln!("Failed to spawn a thread for {addr}: {e}");
+        }
+    }
+}
+
+pub(crate) struct ClientSlot(pub(crate) Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// How a client's connection ended, for either backend.
+pub(crate) fn log_disconnect(config: &Config, addr: SocketAddr, result: io::Result<()>) {
+    let timed_out = |e: &io::Error| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    };
+    match result {
+        Ok(()) if config.log_clients => eprintln!("Client Disconnected"),
+        Err(e) if timed_out(&e) && config.log_clients => {
+            eprintln!("Client {addr} idle, disconnected")
+        }
+        Err(e) if !timed_out(&e) => eprintln!("Error handling client: {}", e),
+        _ => {}
+    }
+}
+
+/// Answers each line the client sends until it disconnects or sends `QUIT`. Lines
+/// that can't be read, or name an unknown command, get an `ERR` reply.
+pub fn handle_client(
+    stream: TcpStream,
+    dispatcher: &Dispatcher,
+    config: &Config,
+) -> io::Result<()> {
+    let mut lines = LineReader::new(&stream, config.max_line_len);
+    let mut writer = &stream;
+    let mut limiter = RateLimiter::new(config.rate_limit);
+
+    loop {
+        let line = match lines.read_line() {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) => return Ok(()),
+            Err(CodecError::Io(e)) => return Err(e),
+            Err(CodecError::LineTooLong) => Err(CommandError::LineTooLong),
+            Err(CodecError::InvalidUtf8) => Err(CommandError::InvalidUtf8),
+        };
+        let Some(reply) = respond(dispatcher, &mut limiter, line) else {
+            continue;
+        };
+
+        writer.write_all(reply.to_text().as_bytes())?;
+        if reply.closes() {
+            return Ok(());
+        }
+    }
+}
+
+/// The reply to a line read by either backend, `None` for blank lines.
+pub(crate) fn respond(
+    dispatcher: &Dispatcher,
+    limiter: &mut RateLimiter,
+    line: Result<String, CommandError>,
+) -> Option<Reply> {
+    let result = match line {
+        Ok(line) if line.trim().is_empty() => return None,
+        Ok(_) if !limiter.allow() => Err(CommandError::RateLimited),
+        Ok(line) => dispatcher.dispatch(&line),
+        Err(e) => Err(e),
+    };
+    Some(result.unwrap_or_else(|e| Reply::Line(e.to_string())))
+}
+
+/// Serves RESP requests against `store` until the client disconnects. Every request
+/// already buffered is answered in a single write, so pipelining clients don't wait
+/// on a round trip per request. A protocol error is reported, then the connection
+/// closed.
+pub fn handle_resp_client(stream: TcpStream, store: &Store, config: &Config) -> io::Result<()> {
+    let (mut reader, mut writer) = (&stream, &stream);
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let mut limiter = RateLimiter::new(config.rate_limit);
+    let mut decoder = Decoder::new(config.max_bulk_len, config.max_request_len);
+
+    loop {
+        let mut replies = Vec::new();
+        let mut consumed = 0;
+        loop {
+            match decoder.decode(&buf[consumed..]) {
+                Ok((len, Some(request))) => {
+                    consumed += len;
+                    if request == Value::Array(Vec::new()) {
+                        continue;
+                    }
+                    if !limiter.allow() {
+                        Value::error("ERR rate limit exceeded, slow down").encode(&mut replies);
+                        continue;
+                    }
+                    if let Some(reply) = kv::handle(store, request) {
+                        reply.encode(&mut replies);
+                    }
+                }
+                Ok((len, None)) => {
+                    consumed += len;
+                    break;
+                }
+                Err(e) => {
+                    Value::error(e.to_string()).encode(&mut replies);
+                    return writer.write_all(&replies);
+                }
+            }
+        }
+        buf.drain(..consumed);
+        writer.write_all(&replies)?;
+
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[test]
+fn loopback_session() {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        thread,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let config = Config {
+            max_line_len: 16,
+            ..Config::default()
+        };
+        handle_client(stream, &Dispatcher::default(), &config).unwrap();
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    // Split and coalesced writes frame the same way
+    client.write_all(b"DOU").unwrap();
+    client.flush().unwrap();
+    thread::sleep(std::time::Duration::from_millis(20));
+    client
+        .write_all(b"BLE 21\r\nPING\n\nECHO hi there\nDOUBLE nope\n")
+        .unwrap();
+    client
+        .write_all(b"ECHO this line is way too long\nBOGUS\nQUIT\nPING\n")
+        .unwrap();
+
+    let replies = BufReader::new(&client)
+        .lines()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        replies,
+        [
+            "42",
+            "PONG",
+            "hi there",
+            "ERR INVALID_ARGUMENT 'nope' is not an integer",
+            "ERR LINE_TOO_LONG line too long",
+            "ERR UNKNOWN_COMMAND unknown command 'BOGUS', try HELP",
+            "BYE",
+        ]
+    );
+
+    server.join().unwrap();
+}
+
+/// A RESP server on a loopback port, sharing the store it hands back.
+#[cfg(test)]
+fn spawn_resp_server() -> (SocketAddr, Arc<Store>) {
+    let store = Arc::new(Store::new());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_store = Arc::clone(&store);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let store = Arc::clone(&server_store);
+            thread::spawn(move || {
+                let config = Config {
+                    max_bulk_len: 1024,
+                    ..Config::default()
+                };
+                let _ = handle_resp_client(stream.unwrap(), &store, &config);
+            });
+        }
+    });
+    (addr, store)
+}
+
+#[cfg(test)]
+fn resp_client(addr: SocketAddr) -> TcpStream {
+    let client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client
+}
+
+/// Sends raw bytes, reads until `expected` bytes came back.
+#[cfg(test)]
+fn roundtrip(client: &mut TcpStream, request: &[u8], expected: &[u8]) {
+    client.write_all(request).unwrap();
+    let mut reply = vec![0; expected.len()];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&reply),
+        String::from_utf8_lossy(expected),
+        "reply to {}",
+        String::from_utf8_lossy(request)
+    );
+}
+
+#[test]
+fn resp_over_loopback() {
+    let (addr, store) = spawn_resp_server();
+    let client = &mut resp_client(addr);
+
+    roundtrip(client, b"*1\r\n$4\r\nPING\r\n", b"+PONG\r\n");
+    roundtrip(client, b"*2\r\n$4\r\nping\r\n$2\r\nhi\r\n", b"$2\r\nhi\r\n");
+    roundtrip(client, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n", b"$-1\r\n");
+    roundtrip(
+        client,
+        b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$6\r\nva\r\nue\r\n",
+        b"+OK\r\n",
+    );
+    roundtrip(
+        client,
+        b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
+        b"$6\r\nva\r\nue\r\n",
+    );
+    // Every connection works on the one store
+    assert_eq!(store.get(b"key"), Some(b"va\r\nue".to_vec()));
+    roundtrip(
+        &mut resp_client(addr),
+        b"*2\r\n$3\r\nDEL\r\n$3\r\nkey\r\n",
+        b":1\r\n",
+    );
+    roundtrip(client, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n", b"$-1\r\n");
+}
+
+#[test]
+fn resp_pipelining() {
+    let (addr, store) = spawn_resp_server();
+    store.set(b"key".to_vec(), b"value".to_vec(), None);
+    let client = &mut resp_client(addr);
+
+    // Split in the middle of a bulk string
+    client
+        .write_all(b"*2\r\n$4\r\nINCR\r\n$1\r\nn\r\n*2\r\n$4\r\nINCR\r\n$1")
+        .unwrap();
+    thread::sleep(Duration::from_millis(20));
+    roundtrip(
+        client,
+        b"\r\nn\r\n*2\r\n$4\r\nINCR\r\n$3\r\nkey\r\n",
+        b":1\r\n:2\r\n-ERR value is not an integer or out of range\r\n",
+    );
+    roundtrip(
+        client,
+        b"EXPIRE n 100\r\nEXPIRE missing 1\r\n",
+        b":1\r\n:0\r\n",
+    );
+}
+
+#[test]
+fn resp_inline_commands() {
+    let (addr, store) = spawn_resp_server();
+    let client = &mut resp_client(addr);
+
+    // As redis-cli sends when typed into directly
+    roundtrip(client, b"SET short v PX 50\r\n", b"+OK\r\n");
+    roundtrip(client, b"set long v EX 100\r\n", b"+OK\r\n");
+    let keys = |client: &mut TcpStream| {
+        client.write_all(b"KEYS *o*\r\n").unwrap();
+        let mut reply = [0; 64];
+        let n = client.read(&mut reply).unwrap();
+        let mut keys = String::from_utf8_lossy(&reply[..n])
+            .split("\r\n")
+            .filter(|line| !line.is_empty() && !line.starts_with(['*', '$']))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    };
+    assert_eq!(keys(client), ["long", "short"]);
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(keys(client), ["long"]);
+    roundtrip(client, b"GET short\r\n", b"$-1\r\n");
+    roundtrip(client, b"DEL long missing\r\n", b":1\r\n");
+
+    roundtrip(client, b"SET k v XX\r\n", b"-ERR syntax error\r\n");
+    roundtrip(
+        client,
+        b"FLUSHALL now\r\n",
+        b"-ERR unknown command 'flushall', with args beginning with: 'now'\r\n",
+    );
+    // Blank lines get no reply at all
+    roundtrip(client, b"\r\nPING\r\n", b"+PONG\r\n");
+    assert!(store.keys(b"*").is_empty());
+}
+
+#[test]
+fn resp_protocol_error_closes() {
+    let (addr, _store) = spawn_resp_server();
+    let client = &mut resp_client(addr);
+
+    roundtrip(client, b"PING\r\n", b"+PONG\r\n");
+    roundtrip(
+        client,
+        b"$2000\r\n",
+        b"-ERR Protocol error: request too large\r\n",
+    );
+    let mut rest = Vec::new();
+    assert_eq!(client.read_to_end(&mut rest).unwrap(), 0);
+}
+
+/// A line protocol server on a loopback port.
+#[cfg(test)]
+fn spawn_line_server(config: Config) -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0", config).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(Arc::new(Dispatcher::default())));
+    addr
+}
+
+#[cfg(test)]
+fn line_client(addr: SocketAddr) -> io::BufReader<TcpStream> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    io::BufReader::new(stream)
+}
+
+/// The next line without its terminator, empty once the server hung up.
+#[cfg(test)]
+fn next_line(client: &mut io::BufReader<TcpStream>) -> String {
+    use std::io::BufRead;
+
+    let mut line = String::new();
+    client.read_line(&mut line).unwrap();
+    line.trim_end().to_string()
+}
+
+#[test]
+fn busy_when_full() {
+    let addr = spawn_line_server(Config {
+        max_clients: 1,
+        ..Config::default()
+    });
+
+    let mut first = line_client(addr);
+    first.get_mut().write_all(b"PING\n").unwrap();
+    assert_eq!(next_line(&mut first), "PONG");
+
+    // The only slot is taken
+    let mut second = line_client(addr);
+    assert_eq!(
+        next_line(&mut second),
+        "ERR BUSY too many clients, try again later"
+    );
+    assert_eq!(next_line(&mut second), "");
+
+    first.get_mut().write_all(b"QUIT\n").unwrap();
+    assert_eq!(next_line(&mut first), "BYE");
+    assert_eq!(next_line(&mut first), "");
+    // The slot is freed just after the connection closes, retry until it is
+    let mut third = (0..50)
+        .find_map(|_| {
+            let mut client = line_client(addr);
+            let _ = client.get_mut().write_all(b"PING\n");
+            match next_line(&mut client).as_str() {
+                "PONG" => Some(client),
+                _ => {
+                    thread::sleep(Duration::from_millis(10));
+                    None
+                }
+            }
+        })
+        .expect("the slot is never freed");
+    third.get_mut().write_all(b"QUIT\n").unwrap();
+    assert_eq!(next_line(&mut third), "BYE");
+}
+
+#[test]
+fn rate_limited() {
+    let addr = spawn_line_server(Config {
+        rate_limit: Some(RateLimit {
+            per_second: 1.0,
+            burst: 3,
+        }),
+        ..Config::default()
+    });
+
+    let mut client = line_client(addr);
+    client
+        .get_mut()
+        .write_all(b"PING\nPING\nPING\nPING\n")
+        .unwrap();
+    assert_eq!(next_line(&mut client), "PONG");
+    assert_eq!(next_line(&mut client), "PONG");
+    assert_eq!(next_line(&mut client), "PONG");
+    assert_eq!(
+        next_line(&mut client),
+        "ERR RATE_LIMITED too many commands, slow down"
+    );
+}
+
+#[test]
+fn idle_clients_are_dropped() {
+    let addr = spawn_line_server(Config {
+        idle_timeout: Some(Duration::from_millis(100)),
+        ..Config::default()
+    });
+
+    let mut client = line_client(addr);
+    client.get_mut().write_all(b"PING\n").unwrap();
+    assert_eq!(next_line(&mut client), "PONG");
+    assert_eq!(next_line(&mut client), "");
+}
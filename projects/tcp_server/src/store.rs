@@ -0,0 +1,288 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    NotAnInteger,
+    Overflow,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotAnInteger => write!(f, "value is not an integer or out of range"),
+            StoreError::Overflow => write!(f, "increment or decrement would overflow"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// In-memory keys and values, shared by every connection.
+///
+/// Expired keys are dropped when they're next touched, and by `purge_expired` for
+/// the ones nobody asks for again.
+#[derive(Default)]
+pub struct Store {
+    entries: Mutex<HashMap<Vec<u8>, Entry>>,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().unwrap();
+        live_entry(&mut entries, key).map(|entry| entry.value.clone())
+    }
+
+    /// Replaces the value and any expiry the key had.
+    pub fn set(&self, key: Vec<u8>, value: Vec<u8>, expires_at: Option<Instant>) {
+        let entry = Entry { value, expires_at };
+        self.entries.lock().unwrap().insert(key, entry);
+    }
+
+    /// Whether the key was there.
+    pub fn del(&self, key: &[u8]) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        live_entry(&mut entries, key).is_some() && entries.remove(key).is_some()
+    }
+
+    /// Adds one to the integer at `key`, starting from 0. The expiry is kept.
+    pub fn incr(&self, key: &[u8]) -> Result<i64, StoreError> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = live_entry(&mut entries, key) else {
+            entries.insert(
+                key.to_vec(),
+                Entry {
+                    value: b"1".to_vec(),
+                    expires_at: None,
+                },
+            );
+            return Ok(1);
+        };
+
+        let value = std::str::from_utf8(&entry.value)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or(StoreError::NotAnInteger)?;
+        let value = value.checked_add(1).ok_or(StoreError::Overflow)?;
+        entry.value = value.to_string().into_bytes();
+        Ok(value)
+    }
+
+    /// Whether the key was there to expire. A time in the past removes it.
+    pub fn expire(&self, key: &[u8], at: Instant) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match live_entry(&mut entries, key) {
+            Some(entry) => {
+                entry.expires_at = Some(at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Live keys matching a glob pattern, in no particular order.
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Vec<u8>> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|(key, entry)| !entry.is_expired(now) && glob_match(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Drops every expired key, returns how many there were.
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| !entry.is_expired(now));
+        before - entries.len()
+    }
+
+    /// Purges expired keys every `interval`, until the store is dropped.
+    pub fn spawn_expirer(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let store = Arc::downgrade(self);
+        thread::Builder::new()
+            .name("expirer".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                match store.upgrade() {
+                    Some(store) => store.purge_expired(),
+                    None => return,
+                };
+            })
+            .expect("failed to spawn the expirer thread")
+    }
+}
+
+/// The entry at `key`, unless it's missing or expired, which removes it.
+fn live_entry<'a>(entries: &'a mut HashMap<Vec<u8>, Entry>, key: &[u8]) -> Option<&'a mut Entry> {
+    if entries
+        .get(key)
+        .is_some_and(|entry| entry.is_expired(Instant::now()))
+    {
+        entries.remove(key);
+    }
+    entries.get_mut(key)
+}
+
+/// Redis style glob: `*`, `?`, `[abc]`, `[^a-z]` and `\` to escape.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` when the rest stops matching
+    let mut star = None;
+
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, t));
+        } else if let Some(len) = match_one(&pattern[p..], text[t]) {
+            p += len;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// How many pattern bytes matched `c`, if the next token does.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    match *pattern {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'\\', escaped, ..] => (escaped == c).then_some(2),
+        [b'[', ..] => match_class(pattern, c),
+        [literal, ..] => (literal == c).then_some(1),
+    }
+}
+
+fn match_class(pattern: &[u8], c: u8) -> Option<usize> {
+    let negate = pattern.get(1) == Some(&b'^');
+    let mut i = if negate { 2 } else { 1 };
+    let mut matched = false;
+
+    loop {
+        match pattern[i..] {
+            // Never closed, so not a class
+            [] => return (c == b'[').then_some(1),
+            [b']', ..] => break,
+            [b'\\', escaped, ..] => {
+                matched |= escaped == c;
+                i += 2;
+            }
+            [from, b'-', to, ..] if to != b']' => {
+                matched |= (from.min(to)..=from.max(to)).contains(&c);
+                i += 3;
+            }
+            [b, ..] => {
+                matched |= b == c;
+                i += 1;
+            }
+        }
+    }
+    (matched != negate).then_some(i + 1)
+}
+
+#[test]
+fn expiry() {
+    let store = Store::new();
+    let soon = Instant::now() + Duration::from_millis(30);
+    store.set(b"a".to_vec(), b"1".to_vec(), Some(soon));
+    store.set(b"b".to_vec(), b"2".to_vec(), Some(soon));
+    store.set(b"c".to_vec(), b"41".to_vec(), None);
+    store.set(b"new".to_vec(), b"1".to_vec(), None);
+
+    assert_eq!(store.incr(b"a"), Ok(2));
+    assert!(store.expire(b"c", Instant::now() + Duration::from_secs(60)));
+    assert!(!store.expire(b"missing", Instant::now()));
+
+    thread::sleep(Duration::from_millis(40));
+    // Lazily, the expiry was kept by INCR
+    assert_eq!(store.get(b"a"), None);
+    assert!(!store.del(b"a"));
+    // And periodically
+    assert_eq!(store.purge_expired(), 1);
+    let mut keys = store.keys(b"*");
+    keys.sort();
+    assert_eq!(keys, [b"c".to_vec(), b"new".to_vec()]);
+
+    assert!(store.expire(b"c", Instant::now()));
+    assert_eq!(store.get(b"c"), None);
+    assert!(store.del(b"new"));
+}
+
+#[test]
+fn incr() {
+    let store = Store::new();
+    store.set(b"c".to_vec(), b"41".to_vec(), None);
+    assert_eq!(store.incr(b"c"), Ok(42));
+    assert_eq!(store.get(b"c"), Some(b"42".to_vec()));
+    assert_eq!(store.incr(b"new"), Ok(1));
+
+    store.set(b"x".to_vec(), b"nope".to_vec(), None);
+    assert_eq!(store.incr(b"x"), Err(StoreError::NotAnInteger));
+    store.set(b"x".to_vec(), i64::MAX.to_string().into_bytes(), None);
+    assert_eq!(store.incr(b"x"), Err(StoreError::Overflow));
+}
+
+#[test]
+fn glob_patterns() {
+    for (pattern, text, expected) in [
+        ("*", "", true),
+        ("*", "anything", true),
+        ("user:*", "user:42", true),
+        ("user:*", "users:42", false),
+        ("h?llo", "hello", true),
+        ("h?llo", "hllo", false),
+        ("h*llo", "heeeello", true),
+        ("h*l*o", "hello", true),
+        ("h*lo", "hello world", false),
+        ("h[ae]llo", "hallo", true),
+        ("h[ae]llo", "hillo", false),
+        ("h[^e]llo", "hallo", true),
+        ("h[^e]llo", "hello", false),
+        ("h[a-c]llo", "hbllo", true),
+        ("h[c-a]llo", "hbllo", true),
+        ("h[a-c]llo", "hdllo", false),
+        ("h\\*llo", "h*llo", true),
+        ("h\\*llo", "hello", false),
+        ("h[llo", "h[llo", true),
+        (
+            "a*a*a*a*a*b",
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            false,
+        ),
+    ] {
+        assert_eq!(
+            glob_match(pattern.as_bytes(), text.as_bytes()),
+            expected,
+            "{pattern} against {text}"
+        );
+    }
+}
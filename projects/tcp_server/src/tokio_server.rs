@@ -0,0 +1,216 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time,
+};
+
+use crate::{
+    codec::{AsyncLineReader, CodecError},
+    command::{CommandError, Dispatcher},
+    limit::RateLimiter,
+    server::{log_disconnect, respond, ClientSlot, Config, Handler, ACCEPT_BACKOFF},
+};
+
+/// Serves the plain text commands like `server::Server` does, from tokio tasks instead
+/// of a thread per client.
+pub struct TokioServer {
+    listener: TcpListener,
+    config: Config,
+    clients: Arc<AtomicUsize>,
+}
+
+impl TokioServer {
+    pub async fn bind(addr: impl ToSocketAddrs, config: Config) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            config,
+            clients: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients forever. Failing to accept one is logged, not fatal.
+    pub async fn run(&self, dispatcher: Arc<Dispatcher>) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, addr)) => self.serve(stream, addr, &dispatcher),
+                Err(e) => {
+                    eprintln!("Failed to accept a client: {e}");
+                    time::sleep(ACCEPT_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    fn serve(&self, mut stream: TcpStream, addr: SocketAddr, dispatcher: &Arc<Dispatcher>) {
+        let connected = self.clients.fetch_add(1, Ordering::AcqRel);
+        let slot = ClientSlot(Arc::clone(&self.clients));
+        if connected >= self.config.max_clients {
+            drop(slot);
+            eprintln!("Turned {addr} away, {connected} clients connected");
+            let busy = dispatcher.busy_message();
+            tokio::spawn(async move {
+                let _ = stream.write_all(&busy).await;
+                let _ = stream.shutdown().await;
+            });
+            return;
+        }
+        if self.config.log_clients {
+            println!("Client Connected");
+        }
+
+        let config = self.config;
+        let dispatcher = Arc::clone(dispatcher);
+        tokio::spawn(async move {
+            let _slot = slot;
+            let result = handle_client(stream, &dispatcher, &config).await;
+            log_disconnect(&config, addr, result);
+        });
+    }
+}
+
+/// `server::handle_client`, for a tokio stream.
+pub async fn handle_client(
+    mut stream: TcpStream,
+    dispatcher: &Dispatcher,
+    config: &Config,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut lines = AsyncLineReader::new(reader, config.max_line_len);
+    let mut limiter = RateLimiter::new(config.rate_limit);
+
+    loop {
+        let line = match idle_timeout(config.idle_timeout, lines.read_line()).await {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) => return Ok(()),
+            Err(CodecError::Io(e)) => return Err(e),
+            Err(CodecError::LineTooLong) => Err(CommandError::LineTooLong),
+            Err(CodecError::InvalidUtf8) => Err(CommandError::InvalidUtf8),
+        };
+        let Some(reply) = respond(dispatcher, &mut limiter, line) else {
+            continue;
+        };
+
+        idle_timeout(
+            config.idle_timeout,
+            writer.write_all(reply.to_text().as_bytes()),
+        )
+        .await?;
+        if reply.closes() {
+            return writer.shutdown().await;
+        }
+    }
+}
+
+/// Fails with `TimedOut` when `io` takes longer than `limit`, like the blocking
+/// socket timeouts do.
+async fn idle_timeout<T, E: From<io::Error>>(
+    limit: Option<Duration>,
+    io: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    match limit {
+        Some(limit) => time::timeout(limit, io)
+            .await
+            .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut).into())),
+        None => io.await,
+    }
+}
+
+#[cfg(test)]
+async fn spawn_server(config: Config) -> SocketAddr {
+    let config = Config {
+        log_clients: false,
+        ..config
+    };
+    let server = TokioServer::bind("127.0.0.1:0", config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.run(Arc::new(Dispatcher::default())).await });
+    addr
+}
+
+#[cfg(test)]
+async fn next_line(client: &mut tokio::io::BufReader<TcpStream>) -> String {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut line = String::new();
+    client.read_line(&mut line).await.unwrap();
+    line
+}
+
+#[tokio::test]
+async fn same_protocol_on_tokio() {
+    let addr = spawn_server(Config {
+        max_line_len: 16,
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = tokio::io::BufReader::new(TcpStream::connect(addr).await.unwrap());
+    client
+        .get_mut()
+        .write_all(b"DOUBLE 21\r\nECHO hi\n\nDOUBLE x\nECHO much too long a line\nBOGUS\n")
+        .await
+        .unwrap();
+    let mut replies = Vec::new();
+    for _ in 0..5 {
+        replies.push(next_line(&mut client).await);
+    }
+    assert_eq!(
+        replies,
+        [
+            "42\n",
+            "hi\n",
+            "ERR INVALID_ARGUMENT 'x' is not an integer\n",
+            "ERR LINE_TOO_LONG line too long\n",
+            "ERR UNKNOWN_COMMAND unknown command 'BOGUS', try HELP\n",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn busy_when_full() {
+    let addr = spawn_server(Config {
+        max_clients: 1,
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = tokio::io::BufReader::new(TcpStream::connect(addr).await.unwrap());
+    client.get_mut().write_all(b"PING\n").await.unwrap();
+    assert_eq!(next_line(&mut client).await, "PONG\n");
+
+    let mut busy = tokio::io::BufReader::new(TcpStream::connect(addr).await.unwrap());
+    assert_eq!(
+        next_line(&mut busy).await,
+        "ERR BUSY too many clients, try again later\n"
+    );
+    assert_eq!(next_line(&mut busy).await, "");
+}
+
+#[tokio::test]
+async fn idle_clients_are_dropped() {
+    let addr = spawn_server(Config {
+        idle_timeout: Some(Duration::from_millis(100)),
+        ..Config::default()
+    })
+    .await;
+
+    let mut client = tokio::io::BufReader::new(TcpStream::connect(addr).await.unwrap());
+    client.get_mut().write_all(b"PING\n").await.unwrap();
+    assert_eq!(next_line(&mut client).await, "PONG\n");
+    assert_eq!(next_line(&mut client).await, "");
+}
@@ -1,87 +1,541 @@
-use std::net::SocketAddr;
+use serde_json::json;
+use std::{env, net::SocketAddr, time::Duration};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncWriteExt, BufReader},
     net::{TcpListener, TcpStream},
+    task::JoinSet,
+    time::timeout,
 };
-use tracing::{debug, error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
-const API_URL: &str = "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd";
+use crate::{
+    breaker::{BreakerConfig, CircuitBreaker},
+    cache::{CacheConfig, PriceCache},
+    feed::{FeedConfig, PriceFeed},
+    http::{read_request, HttpError, Request, Response},
+    price::{HttpPriceProvider, PriceError, PriceProvider, DEFAULT_BASE_URL},
+    ws,
+};
+
+/// How long a connection may sit between requests before it's closed.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long requests still being answered get once shutdown starts, after that
+/// their connections are dropped.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub async fn run() -> eyre::Result<()> {
+pub async fn run(shutdown: CancellationToken) -> eyre::Result<()> {
     info!("Starting the API, listening on port 3737");
 
+    // Any API answering like CoinGecko's will do
+    let base_url = env::var("PRICE_API_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+    let mut config = CacheConfig::default();
+    if let Some(ttl) = env::var("PRICE_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+    {
+        config.ttl = Duration::from_secs(ttl);
+    }
+    let upstream = CircuitBreaker::new(HttpPriceProvider::new(base_url), BreakerConfig::default());
+    let prices = PriceCache::new(upstream, config);
+    // Stopped along with this attempt, or every restart would leave a poller behind
+    let feed_shutdown = shutdown.child_token();
+    let _stop_feed = feed_shutdown.clone().drop_guard();
+    let feed = PriceFeed::start(prices.clone(), FeedConfig::default(), feed_shutdown);
+
     let listener = TcpListener::bind("127.0.0.1:3737").await?;
+    serve(listener, prices, feed, shutdown).await
+}
 
+/// Accepts connections until `shutdown` is cancelled, then lets those with a
+/// request in flight finish it, for up to `DRAIN_TIMEOUT`.
+pub async fn serve<P: PriceProvider>(
+    listener: TcpListener,
+    prices: PriceCache<P>,
+    feed: PriceFeed,
+    shutdown: CancellationToken,
+) -> eyre::Result<()> {
+    let mut connections = JoinSet::new();
     loop {
-        match listener.accept().await {
-            Ok((mut stream, client_addr)) => {
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(&mut stream, client_addr).await {
-                        error!("Error handling connection: {:?}", e);
-                    }
-                });
-            }
-            Err(_) => {
-                debug!("Failed to accept connection");
-            }
-        };
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, client_addr)) => {
+                    let prices = prices.clone();
+                    let feed = feed.clone();
+                    let shutdown = shutdown.clone();
+                    connections.spawn(async move {
+                        if let Err(e) =
+                            handle_connection(stream, client_addr, &prices, &feed, &shutdown).await
+                        {
+                            error!("Error handling connection: {:?}", e);
+                        }
+                    });
+                }
+                Err(_) => {
+                    debug!("Failed to accept connection");
+                }
+            },
+            // Reaped as they end, so the set only holds the open ones
+            Some(_) = connections.join_next() => (),
+            _ = shutdown.cancelled() => break,
+        }
+    }
+
+    drop(listener);
+    info!(
+        "Shutting down the API, draining {} connections",
+        connections.len()
+    );
+    let drained = timeout(DRAIN_TIMEOUT, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        warn!(
+            "Dropping {} connections still open after {DRAIN_TIMEOUT:?}",
+            connections.len()
+        );
+        connections.shutdown().await;
     }
+    Ok(())
 }
 
-async fn handle_connection(stream: &mut TcpStream, client_addr: SocketAddr) -> eyre::Result<()> {
+async fn handle_connection<P: PriceProvider>(
+    mut stream: TcpStream,
+    client_addr: SocketAddr,
+    prices: &PriceCache<P>,
+    feed: &PriceFeed,
+    shutdown: &CancellationToken,
+) -> eyre::Result<()> {
     debug!("New Connection From: {:?}", client_addr);
-    let mut buffer = [0; 1024];
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+
     loop {
-        let read_client = stream.read(buffer.as_mut()).await?;
-        let bytes_read = match read_client {
-            0 => {
+        let next = tokio::select! {
+            next = timeout(KEEP_ALIVE_TIMEOUT, read_request(&mut reader)) => next,
+            _ = shutdown.cancelled() => {
+                debug!("Shutting Down, Closing");
+                return Ok(());
+            }
+        };
+        let request = match next {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) => {
                 debug!("Connection Closed");
                 return Ok(());
             }
-            bytes => bytes,
+            Err(_) => {
+                debug!("Connection Idle, Closing");
+                return Ok(());
+            }
+            Ok(Err(HttpError::Io(e))) => return Err(e.into()),
+            // The rest of the request can't be trusted, so the connection ends here
+            Ok(Err(e)) => {
+                debug!("Invalid Request: {e}");
+                e.response().write_to(&mut writer, false).await?;
+                writer.shutdown().await?;
+                return Ok(());
+            }
         };
 
-        let request = String::from_utf8_lossy(&buffer[..bytes_read]);
+        if request.path == "/stream" && ws::is_upgrade(&request) {
+            match ws::handshake(&request) {
+                Ok(response) => {
+                    response.write_to(&mut writer, true).await?;
+                    debug!("GET /stream -> WebSocket");
+                    let read_ahead = reader.buffer().to_vec();
+                    return ws::session(stream, read_ahead, prices, feed, shutdown).await;
+                }
+                Err(reason) => {
+                    Response::text(400, reason)
+                        .write_to(&mut writer, false)
+                        .await?;
+                    writer.shutdown().await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let response = route(&request, client_addr, prices).await;
+        debug!("{} {} -> {}", request.method, request.path, response.status);
+        // Told to close once shutdown started, rather than cut off before the next one
+        let keep_alive = request.keep_alive() && !shutdown.is_cancelled();
+        response.write_to(&mut writer, keep_alive).await?;
+        if !keep_alive {
+            writer.shutdown().await?;
+            return Ok(());
+        }
+    }
+}
 
-        if request.contains("GET / HTTP/1.1") {
-            let response = format!(
-                "HTTP/1.1 200 OK\r\n\r\nHello, World! from Port {}",
-                client_addr.port()
-            );
+async fn route<P: PriceProvider>(
+    request: &Request,
+    client_addr: SocketAddr,
+    prices: &PriceCache<P>,
+) -> Response {
+    let segments = request.path.split('/').skip(1).collect::<Vec<_>>();
+    let allowed = match segments.as_slice() {
+        [""] | ["bitcoin"] | ["price", _, _] | ["stats"] | ["stream"] => "GET",
+        _ => return Response::text(404, "Not Found"),
+    };
+    if request.method != allowed {
+        return Response::text(405, "Method Not Allowed").with_header("Allow", allowed);
+    }
 
-            if let Err(e) = stream.write_all(response.as_bytes()).await {
-                eprintln!("Failed to write to stream: {:?}", e);
-                return Err(e.into());
+    match segments.as_slice() {
+        ["bitcoin"] => match prices.price("bitcoin", "usd").await {
+            Ok(price) => Response::text(200, format!("The price of Bitcoin is ${price}")),
+            Err(PriceError::CircuitOpen { retry_after }) => degraded(
+                Response::text(503, "The price of Bitcoin is unavailable for now."),
+                retry_after,
+            ),
+            Err(e) => {
+                error!("Failed to fetch the price: {e}");
+                Response::text(502, "Failed to get the price of Bitcoin.")
             }
-            break;
+        },
+        ["price", coin, currency] => price(prices, coin, currency).await,
+        ["stream"] => Response::text(426, "Upgrade Required").with_header("Upgrade", "websocket"),
+        ["stats"] => {
+            let stats = prices.stats();
+            Response::json(
+                200,
+                &json!({
+                    "cache": {
+                        "hits": stats.hits,
+                        "stale": stats.stale,
+                        "misses": stats.misses,
+                        "coalesced": stats.coalesced,
+                    }
+                }),
+            )
         }
-        if request.contains("GET /bitcoin HTTP/1.1") {
-            let data = fetch_data(API_URL).await?;
-            let response = process_data(data);
-            let response = format!("HTTP/1.1 200 OK\r\n\r\n{response}");
-
-            if let Err(e) = stream.write_all(response.as_bytes()).await {
-                eprintln!("Failed to write to stream: {:?}", e);
-                return Err(e.into());
-            }
-            break;
+        _ => Response::text(
+            200,
+            format!("Hello, World! from Port {}", client_addr.port()),
+        ),
+    }
+}
+
+/// `GET /price/:coin/:currency`, as JSON.
+async fn price<P: PriceProvider>(provider: &P, coin: &str, currency: &str) -> Response {
+    if !is_valid_id(coin) || !is_valid_id(currency) {
+        return Response::json(
+            400,
+            &json!({ "error": "coin and currency are lowercase ids like bitcoin and usd" }),
+        );
+    }
+
+    match provider.price(coin, currency).await {
+        Ok(price) => Response::json(
+            200,
+            &json!({ "coin": coin, "currency": currency, "price": price }),
+        ),
+        Err(e @ PriceError::Unknown { .. }) => {
+            Response::json(404, &json!({ "error": e.to_string() }))
+        }
+        Err(PriceError::CircuitOpen { retry_after }) => degraded(
+            Response::json(
+                503,
+                &json!({ "error": "upstream unavailable", "degraded": true }),
+            ),
+            retry_after,
+        ),
+        Err(e) => {
+            error!("Failed to fetch the price of {coin} in {currency}: {e}");
+            Response::json(502, &json!({ "error": "upstream unavailable" }))
         }
     }
+}
 
-    Ok(())
+/// Coin and currency ids are like `bitcoin`, `usd` or `shiba-inu`.
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// For when the upstream isn't even asked, telling clients when to come back.
+fn degraded(response: Response, retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    response.with_header("Retry-After", &seconds.to_string())
 }
 
-async fn fetch_data(url: &str) -> eyre::Result<serde_json::Value> {
-    let response = reqwest::get(url).await?;
-    let body = response.json::<serde_json::Value>().await?;
-    Ok(body)
+/// `serve` on a loopback port, with the streamed prices polled every 50 ms.
+#[cfg(test)]
+pub async fn spawn_serve<P: PriceProvider>(
+    prices: PriceCache<P>,
+    shutdown: CancellationToken,
+) -> (SocketAddr, tokio::task::JoinHandle<eyre::Result<()>>) {
+    let config = FeedConfig {
+        every: Duration::from_millis(50),
+        ..FeedConfig::default()
+    };
+    let feed = PriceFeed::start(prices.clone(), config, shutdown.clone());
+    spawn_serve_with(prices, feed, shutdown).await
 }
 
-fn process_data(data: serde_json::Value) -> String {
-    debug!("data: {data}");
-    if let Some(price) = data["bitcoin"]["usd"].as_f64() {
-        format!("The price of Bitcoin is ${price}")
-    } else {
-        "Failed to get the price of Bitcoin.".to_owned()
+/// `serve` on a loopback port, streaming from `feed`.
+#[cfg(test)]
+pub async fn spawn_serve_with<P: PriceProvider>(
+    prices: PriceCache<P>,
+    feed: PriceFeed,
+    shutdown: CancellationToken,
+) -> (SocketAddr, tokio::task::JoinHandle<eyre::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (addr, tokio::spawn(serve(listener, prices, feed, shutdown)))
+}
+
+#[tokio::test]
+async fn http_over_loopback() {
+    use crate::stub::StubUpstream;
+    use tokio::io::AsyncReadExt;
+
+    let provider = HttpPriceProvider::new(StubUpstream::start(&[]).await.base_url());
+    let (addr, _) = spawn_serve(
+        PriceCache::new(provider, CacheConfig::default()),
+        CancellationToken::new(),
+    )
+    .await;
+
+    /// Sends the chunks with a pause in between, then reads until the server closes.
+    async fn exchange(addr: SocketAddr, chunks: &[&str]) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for chunk in chunks {
+            stream.write_all(chunk.as_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        (stream.local_addr().unwrap().port(), response)
     }
+
+    let (port, response) = exchange(
+        addr,
+        &[
+            "GET / HTTP/1.1\r\nHost: loc",
+            "alhost\r\nConnection: close\r\n\r\n",
+        ],
+    )
+    .await;
+    let body = format!("Hello, World! from Port {port}");
+    assert_eq!(
+        response,
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    );
+
+    // Kept alive across requests, the body of the first one is skipped over
+    let (_, response) = exchange(
+        addr,
+        &[
+            "POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nfirst ",
+            "bitGET /missing HTTP/1.1\r\n\r\n",
+            "DELETE /bitcoin HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ],
+    )
+    .await;
+    let statuses = response
+        .split("HTTP/1.1 ")
+        .filter_map(|response| response.lines().next())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        [
+            "405 Method Not Allowed",
+            "404 Not Found",
+            "405 Method Not Allowed"
+        ]
+    );
+    assert_eq!(response.matches("Allow: GET\r\n").count(), 2);
+    assert_eq!(response.matches("Connection: keep-alive\r\n").count(), 2);
+    assert!(response.ends_with("Connection: close\r\n\r\nMethod Not Allowed"));
+
+    // A malformed request gets an answer, then the connection is closed
+    let (_, response) = exchange(addr, &["GARBAGE\r\n\r\nGET / HTTP/1.1\r\n\r\n"]).await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.ends_with("Bad request: malformed request line"));
+}
+
+#[tokio::test]
+async fn price_endpoint() {
+    use crate::stub::StubUpstream;
+    use tokio::io::AsyncReadExt;
+
+    let stub =
+        StubUpstream::start(&[("bitcoin", "usd", 97000.5), ("ethereum", "eur", 3100.25)]).await;
+    let provider = HttpPriceProvider::new(stub.base_url());
+    let (addr, _) = spawn_serve(
+        PriceCache::new(provider, CacheConfig::default()),
+        CancellationToken::new(),
+    )
+    .await;
+
+    let get = |path: &'static str| async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head[9..12].parse::<u16>().unwrap();
+        (status, head.to_string(), body.to_string())
+    };
+
+    let (status, head, body) = get("/price/ethereum/eur").await;
+    assert_eq!(status, 200);
+    assert!(head.contains("Content-Type: application/json\r\n"));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+        json!({ "coin": "ethereum", "currency": "eur", "price": 3100.25 })
+    );
+
+    let (status, _, body) = get("/price/dogecoin/usd").await;
+    assert_eq!(status, 404);
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+        json!({ "error": "no price for dogecoin in usd" })
+    );
+    assert_eq!(get("/price/Bit&coin/usd").await.0, 400);
+    assert_eq!(get("/price/bitcoin").await.0, 404);
+    assert_eq!(get("/bitcoin").await.2, "The price of Bitcoin is $97000.5");
+    assert_eq!(get("/bitcoin").await.2, "The price of Bitcoin is $97000.5");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&get("/stats").await.2).unwrap(),
+        json!({ "cache": { "hits": 1, "stale": 0, "misses": 3, "coalesced": 0 } })
+    );
+
+    // An upstream that's gone
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let gone = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let provider = HttpPriceProvider::new(gone);
+    let (addr, _) = spawn_serve(
+        PriceCache::new(provider, CacheConfig::default()),
+        CancellationToken::new(),
+    )
+    .await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /price/bitcoin/usd HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    assert!(response.ends_with(r#"{"error":"upstream unavailable"}"#));
+}
+
+#[tokio::test]
+async fn shutdown_drains_connections() {
+    use tokio::io::AsyncReadExt;
+
+    /// Slow enough for shutdown to start while a request waits on it.
+    struct Slow;
+
+    impl PriceProvider for Slow {
+        async fn price(&self, _: &str, _: &str) -> Result<f64, PriceError> {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(42.0)
+        }
+    }
+
+    let shutdown = CancellationToken::new();
+    let (addr, server) = spawn_serve(
+        PriceCache::new(Slow, CacheConfig::default()),
+        shutdown.clone(),
+    )
+    .await;
+
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    let mut busy = TcpStream::connect(addr).await.unwrap();
+    busy.write_all(b"GET /price/bitcoin/usd HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    shutdown.cancel();
+
+    // Closed right away
+    let mut response = String::new();
+    idle.read_to_string(&mut response).await.unwrap();
+    assert_eq!(response, "");
+
+    // Answered, then closed
+    busy.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+
+    timeout(Duration::from_secs(1), server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn degraded_while_upstream_fails() {
+    use crate::{
+        price::FetchConfig,
+        stub::{Fault, StubUpstream},
+    };
+    use tokio::io::AsyncReadExt;
+
+    let stub =
+        StubUpstream::start(&[("bitcoin", "usd", 97000.5), ("bitcoin", "eur", 89000.0)]).await;
+    let provider = HttpPriceProvider::with_config(
+        stub.base_url(),
+        FetchConfig {
+            retries: 1,
+            initial_backoff: Duration::from_millis(10),
+            ..FetchConfig::default()
+        },
+    );
+    let breaker = CircuitBreaker::new(
+        provider,
+        BreakerConfig {
+            failure_threshold: 1,
+            open_for: Duration::from_millis(1500),
+        },
+    );
+    let (addr, _) = spawn_serve(
+        PriceCache::new(breaker, CacheConfig::default()),
+        CancellationToken::new(),
+    )
+    .await;
+
+    let get = |path: &'static str| async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+
+    // A blip is retried away
+    stub.inject([Fault::Status(500)]);
+    assert!(get("/price/bitcoin/eur")
+        .await
+        .starts_with("HTTP/1.1 200 OK\r\n"));
+
+    // An outage opens the circuit, after that the upstream is left alone
+    stub.inject([Fault::Status(503); 2]);
+    assert!(get("/bitcoin")
+        .await
+        .starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    let response = get("/bitcoin").await;
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.contains("Retry-After: 2\r\n"));
+    assert!(response.ends_with("The price of Bitcoin is unavailable for now."));
+    let response = get("/price/ethereum/usd").await;
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.ends_with(r#"{"degraded":true,"error":"upstream unavailable"}"#));
+    assert_eq!(stub.requests(), 4);
 }
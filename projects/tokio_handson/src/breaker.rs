@@ -0,0 +1,172 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::price::{PriceError, PriceProvider};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerConfig {
+    /// Transient failures in a row that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit fails fast before one request is let through to try.
+    pub open_for: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Stops asking another provider once it keeps failing, so callers get an answer
+/// right away instead of waiting out its timeouts and retries.
+pub struct CircuitBreaker<P> {
+    provider: P,
+    config: BreakerConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// One request is trying the provider again, since then.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl<P: PriceProvider> CircuitBreaker<P> {
+    pub fn new(provider: P, config: BreakerConfig) -> Self {
+        Self {
+            provider,
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a request may go through, else how long until one may.
+    fn admit(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if now < until => Err(until - now),
+            // A trial that never came back, maybe cancelled, doesn't keep it half open
+            State::HalfOpen { since } if now < since + self.config.open_for => {
+                Err(since + self.config.open_for - now)
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                *state = State::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    fn record(&self, result: &Result<f64, PriceError>) {
+        let mut state = self.state.lock().unwrap();
+        let failed = matches!(result, Err(e) if e.is_transient());
+        *state = match (*state, failed) {
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            (_, false) => {
+                info!("Upstream is back, closing the circuit");
+                State::Closed { failures: 0 }
+            }
+            (State::Closed { failures }, true) if failures + 1 < self.config.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, true) => {
+                warn!(
+                    "Upstream keeps failing, opening the circuit for {:?}",
+                    self.config.open_for
+                );
+                State::Open {
+                    until: Instant::now() + self.config.open_for,
+                }
+            }
+        };
+    }
+}
+
+impl<P: PriceProvider> PriceProvider for CircuitBreaker<P> {
+    async fn price(&self, coin: &str, currency: &str) -> Result<f64, PriceError> {
+        if let Err(retry_after) = self.admit() {
+            return Err(PriceError::CircuitOpen { retry_after });
+        }
+        let result = self.provider.price(coin, currency).await;
+        self.record(&result);
+        result
+    }
+}
+
+#[tokio::test]
+async fn opens_and_closes_against_stub() {
+    use crate::{
+        price::{FetchConfig, HttpPriceProvider},
+        stub::{Fault, StubUpstream},
+    };
+
+    let stub = StubUpstream::start(&[("bitcoin", "usd", 97000.5)]).await;
+    let provider = HttpPriceProvider::with_config(
+        stub.base_url(),
+        FetchConfig {
+            retries: 0,
+            ..FetchConfig::default()
+        },
+    );
+    let breaker = CircuitBreaker::new(
+        provider,
+        BreakerConfig {
+            failure_threshold: 2,
+            open_for: Duration::from_millis(100),
+        },
+    );
+
+    // Answers that aren't failures of the upstream don't count
+    stub.inject([Fault::Status(503), Fault::Status(404), Fault::Status(503)]);
+    for _ in 0..3 {
+        assert!(breaker.price("bitcoin", "usd").await.is_err());
+    }
+    assert!(breaker.price("bitcoin", "usd").await.is_ok());
+
+    // Open, the upstream isn't asked
+    stub.inject([Fault::Status(503); 3]);
+    for _ in 0..2 {
+        assert!(breaker.price("bitcoin", "usd").await.is_err());
+    }
+    let requests = stub.requests();
+    assert!(matches!(
+        breaker.price("bitcoin", "usd").await,
+        Err(PriceError::CircuitOpen { retry_after }) if retry_after <= Duration::from_millis(100)
+    ));
+    assert_eq!(stub.requests(), requests);
+
+    // Half open, one failed try opens it again
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    assert!(matches!(
+        breaker.price("bitcoin", "usd").await,
+        Err(PriceError::Status(503))
+    ));
+    assert!(matches!(
+        breaker.price("bitcoin", "usd").await,
+        Err(PriceError::CircuitOpen { .. })
+    ));
+    assert_eq!(stub.requests(), requests + 1);
+
+    // And one that works closes it
+    tokio::time::sleep(Duration::from_millis(110)).await;
+    assert!(breaker.price("bitcoin", "usd").await.is_ok());
+    assert!(breaker.price("bitcoin", "usd").await.is_ok());
+    assert_eq!(stub.requests(), requests + 3);
+}
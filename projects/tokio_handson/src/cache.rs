@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::price::{PriceError, PriceProvider};
+
+/// How long prices are kept, after the `Cache-Control` extensions of RFC 5861.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// Served as they are for this long.
+    pub ttl: Duration,
+    /// Past the TTL, still served right away for this long while a fresh price is
+    /// fetched in the background.
+    pub stale_while_revalidate: Duration,
+    /// Past the TTL, still served for this long when fetching a fresh price fails.
+    pub stale_if_error: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(10),
+            stale_while_revalidate: Duration::from_secs(20),
+            stale_if_error: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Answered with a fresh price.
+    pub hits: u64,
+    /// Answered with a stale price, while revalidating or because that failed.
+    pub stale: u64,
+    /// Had to wait for the upstream.
+    pub misses: u64,
+    /// Misses that waited on a request another one had started.
+    pub coalesced: u64,
+}
+
+/// Caches another provider's prices. However many requests miss at once, the
+/// upstream only gets one request per coin and currency.
+pub struct PriceCache<P> {
+    inner: Arc<Inner<P>>,
+}
+
+impl<P> Clone for PriceCache<P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+struct Inner<P> {
+    provider: P,
+    config: CacheConfig,
+    entries: Mutex<HashMap<Key, Entry>>,
+    hits: AtomicU64,
+    stale: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+/// Coin and currency.
+type Key = (String, String);
+
+/// `None` until the fetch is done.
+type Fetch = watch::Receiver<Option<Result<f64, PriceError>>>;
+
+#[derive(Default)]
+struct Entry {
+    /// The last price fetched, and when.
+    price: Option<(f64, Instant)>,
+    fetch: Option<Fetch>,
+}
+
+impl<P: PriceProvider> PriceCache<P> {
+    pub fn new(provider: P, config: CacheConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                provider,
+                config,
+                entries: Mutex::default(),
+                hits: AtomicU64::new(0),
+                stale: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                coalesced: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = &self.inner;
+        CacheStats {
+            hits: inner.hits.load(Ordering::Relaxed),
+            stale: inner.stale.load(Ordering::Relaxed),
+            misses: inner.misses.load(Ordering::Relaxed),
+            coalesced: inner.coalesced.load(Ordering::Relaxed),
+        }
+    }
+
+    fn fetching(&self, entry: &mut Entry, key: &Key) -> (Fetch, bool) {
+        if let Some(fetch) = &entry.fetch {
+            return (fetch.clone(), true);
+        }
+
+        // Spawned, so the fetch completes even if whoever started it goes away
+        let (sender, fetch) = watch::channel(None);
+        entry.fetch = Some(fetch.clone());
+        let inner = Arc::clone(&self.inner);
+        let key = key.clone();
+        tokio::spawn(async move {
+            let result = inner.provider.price(&key.0, &key.1).await;
+            let result = inner.fetched(&key, result);
+            let _ = sender.send(Some(result));
+        });
+        (fetch, false)
+    }
+}
+
+impl<P: PriceProvider> PriceProvider for PriceCache<P> {
+    async fn price(&self, coin: &str, currency: &str) -> Result<f64, PriceError> {
+        let key = (coin.to_string(), currency.to_string());
+        let inner = &self.inner;
+
+        let mut fetch = {
+            let mut entries = inner.entries.lock().unwrap();
+            let entry = entries.entry(key.clone()).or_default();
+            if let Some((price, fetched)) = entry.price {
+                let age = fetched.elapsed();
+                if age < inner.config.ttl {
+                    inner.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(price);
+                }
+                if age < inner.config.ttl + inner.config.stale_while_revalidate {
+                    inner.stale.fetch_add(1, Ordering::Relaxed);
+                    self.fetching(entry, &key);
+                    return Ok(price);
+                }
+            }
+
+            let (fetch, coalesced) = self.fetching(entry, &key);
+            inner.misses.fetch_add(1, Ordering::Relaxed);
+            if coalesced {
+                inner.coalesced.fetch_add(1, Ordering::Relaxed);
+            }
+            fetch
+        };
+
+        let result = fetch
+            .wait_for(Option::is_some)
+            .await
+            .expect("the fetch task sends before it ends");
+        result.clone().expect("waited for a result")
+    }
+}
+
+impl<P> Inner<P> {
+    /// Stores a fetch's result, returns what those waiting on it get.
+    fn fetched(&self, key: &Key, result: Result<f64, PriceError>) -> Result<f64, PriceError> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(key) else {
+            return result;
+        };
+        entry.fetch = None;
+
+        match (result, entry.price) {
+            (Ok(price), _) => {
+                entry.price = Some((price, Instant::now()));
+                Ok(price)
+            }
+            (Err(e), Some((price, fetched))) => {
+                warn!("Failed to refresh the price of {} in {}: {e}", key.0, key.1);
+                if fetched.elapsed() < self.config.ttl + self.config.stale_if_error {
+                    self.stale.fetch_add(1, Ordering::Relaxed);
+                    Ok(price)
+                } else {
+                    Err(e)
+                }
+            }
+            // Nothing worth keeping, like a coin that doesn't exist
+            (Err(e), None) => {
+                entries.remove(key);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn ttl_coalescing_and_stale_prices() {
+    use std::sync::atomic::{AtomicBool, AtomicU32};
+    use tokio::time::sleep;
+
+    #[derive(Default)]
+    struct Upstream {
+        calls: AtomicU32,
+        price: Mutex<f64>,
+        failing: AtomicBool,
+    }
+
+    impl PriceProvider for Arc<Upstream> {
+        async fn price(&self, coin: &str, currency: &str) -> Result<f64, PriceError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(20)).await;
+            if coin != "bitcoin" || self.failing.load(Ordering::SeqCst) {
+                return Err(PriceError::Unknown {
+                    coin: coin.to_string(),
+                    currency: currency.to_string(),
+                });
+            }
+            Ok(*self.price.lock().unwrap())
+        }
+    }
+
+    let upstream = Arc::new(Upstream::default());
+    *upstream.price.lock().unwrap() = 100.0;
+    let cache = PriceCache::new(
+        Arc::clone(&upstream),
+        CacheConfig {
+            ttl: Duration::from_millis(100),
+            stale_while_revalidate: Duration::from_millis(100),
+            stale_if_error: Duration::from_millis(400),
+        },
+    );
+    let calls = || upstream.calls.load(Ordering::SeqCst);
+
+    // A burst of misses is one upstream request
+    let mut burst = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let cache = cache.clone();
+        burst.spawn(async move { cache.price("bitcoin", "usd").await.unwrap() });
+    }
+    while let Some(price) = burst.join_next().await {
+        assert_eq!(price.unwrap(), 100.0);
+    }
+    assert_eq!(calls(), 1);
+    assert_eq!(cache.price("bitcoin", "usd").await.unwrap(), 100.0);
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            hits: 1,
+            stale: 0,
+            misses: 10,
+            coalesced: 9
+        }
+    );
+
+    // Past the TTL, the old price right away while a new one is fetched
+    *upstream.price.lock().unwrap() = 200.0;
+    sleep(Duration::from_millis(110)).await;
+    assert_eq!(cache.price("bitcoin", "usd").await.unwrap(), 100.0);
+    assert_eq!(cache.price("bitcoin", "usd").await.unwrap(), 100.0);
+    sleep(Duration::from_millis(40)).await;
+    assert_eq!(calls(), 2);
+    assert_eq!(cache.price("bitcoin", "usd").await.unwrap(), 200.0);
+
+    // Past revalidating, the old price only if the upstream fails
+    upstream.failing.store(true, Ordering::SeqCst);
+    sleep(Duration::from_millis(250)).await;
+    assert_eq!(cache.price("bitcoin", "usd").await.unwrap(), 200.0);
+    assert_eq!(calls(), 3);
+    sleep(Duration::from_millis(300)).await;
+    assert!(cache.price("bitcoin", "usd").await.is_err());
+
+    // Failures aren't cached
+    assert!(cache.price("dogecoin", "usd").await.is_err());
+    assert!(cache.price("dogecoin", "usd").await.is_err());
+    assert_eq!(calls(), 6);
+    assert_eq!(cache.stats().stale, 3);
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast,
+    time::{interval, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::{cache::PriceCache, price::PriceProvider};
+
+/// The currency streamed prices are in.
+pub const CURRENCY: &str = "usd";
+
+#[derive(Debug, Clone, Copy)]
+pub struct FeedConfig {
+    /// How often the watched coins are polled.
+    pub every: Duration,
+    /// Updates a subscriber can fall behind by before it misses some.
+    pub capacity: usize,
+    /// How often `/stream` clients are pinged. One that hasn't answered by the next
+    /// ping is dropped.
+    pub ping_interval: Duration,
+    /// Coins a `/stream` client may be subscribed to at once. All of them are polled
+    /// one after the other, so there's no letting one client add any number.
+    pub max_subscriptions: usize,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            every: Duration::from_secs(5),
+            capacity: 64,
+            ping_interval: Duration::from_secs(20),
+            max_subscriptions: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceUpdate {
+    pub coin: String,
+    pub price: f64,
+}
+
+/// Polls the prices of the coins anyone is watching, all in one task, and
+/// broadcasts them when they change.
+#[derive(Clone)]
+pub struct PriceFeed {
+    sender: broadcast::Sender<PriceUpdate>,
+    /// How many are watching each coin.
+    watched: Arc<Mutex<HashMap<String, usize>>>,
+    config: FeedConfig,
+}
+
+impl PriceFeed {
+    /// Polls every `config.every` until `shutdown` is cancelled.
+    pub fn start<P: PriceProvider>(
+        prices: PriceCache<P>,
+        config: FeedConfig,
+        shutdown: CancellationToken,
+    ) -> Self {
+        let (sender, _) = broadcast::channel(config.capacity);
+        let feed = Self {
+            sender,
+            watched: Arc::default(),
+            config,
+        };
+
+        let polling = feed.clone();
+        tokio::spawn(async move {
+            let mut ticks = interval(config.every);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut last = HashMap::<String, f64>::new();
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => (),
+                    _ = shutdown.cancelled() => return,
+                }
+                let coins = polling
+                    .watched
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                last.retain(|coin, _| coins.contains(coin));
+                for coin in coins {
+                    match prices.price(&coin, CURRENCY).await {
+                        Ok(price) if last.get(&coin) != Some(&price) => {
+                            last.insert(coin.clone(), price);
+                            // Nobody listening is fine
+                            let _ = polling.sender.send(PriceUpdate { coin, price });
+                        }
+                        Ok(_) => (),
+                        Err(e) => debug!("No update for {coin}: {e}"),
+                    }
+                }
+            }
+        });
+        feed
+    }
+
+    pub fn config(&self) -> &FeedConfig {
+        &self.config
+    }
+
+    /// Broadcasts an update as if it had been polled.
+    #[cfg(test)]
+    pub fn publish(&self, coin: &str, price: f64) {
+        let _ = self.sender.send(PriceUpdate {
+            coin: coin.to_string(),
+            price,
+        });
+    }
+
+    /// Updates for every watched coin, from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<PriceUpdate> {
+        self.sender.subscribe()
+    }
+
+    /// Polls `coin` until it's unwatched as many times.
+    pub fn watch(&self, coin: &str) {
+        *self
+            .watched
+            .lock()
+            .unwrap()
+            .entry(coin.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn unwatch(&self, coin: &str) {
+        let mut watched = self.watched.lock().unwrap();
+        if let Some(count) = watched.get_mut(coin) {
+            *count -= 1;
+            if *count == 0 {
+                watched.remove(coin);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn broadcasts_only_changes() {
+    use crate::{cache::CacheConfig, price::PriceError};
+    use tokio::{
+        sync::broadcast::error::TryRecvError,
+        time::{sleep, timeout},
+    };
+
+    #[derive(Default)]
+    struct Upstream(Mutex<HashMap<String, f64>>);
+
+    impl PriceProvider for Arc<Upstream> {
+        async fn price(&self, coin: &str, currency: &str) -> Result<f64, PriceError> {
+            self.0
+                .lock()
+                .unwrap()
+                .get(coin)
+                .copied()
+                .ok_or_else(|| PriceError::Unknown {
+                    coin: coin.to_string(),
+                    currency: currency.to_string(),
+                })
+        }
+    }
+
+    let upstream = Arc::new(Upstream::default());
+    let set_price = |price| {
+        upstream
+            .0
+            .lock()
+            .unwrap()
+            .insert("bitcoin".to_string(), price);
+    };
+    set_price(1.0);
+    // Every poll goes upstream
+    let prices = PriceCache::new(
+        Arc::clone(&upstream),
+        CacheConfig {
+            ttl: Duration::ZERO,
+            stale_while_revalidate: Duration::ZERO,
+            stale_if_error: Duration::ZERO,
+        },
+    );
+    let shutdown = CancellationToken::new();
+    let feed = PriceFeed::start(
+        prices,
+        FeedConfig {
+            every: Duration::from_millis(10),
+            ..FeedConfig::default()
+        },
+        shutdown.clone(),
+    );
+    let mut updates = feed.subscribe();
+    let update = |price| {
+        Ok(Ok(PriceUpdate {
+            coin: "bitcoin".to_string(),
+            price,
+        }))
+    };
+
+    feed.watch("bitcoin");
+    feed.watch("dogecoin");
+    assert_eq!(
+        timeout(Duration::from_secs(2), updates.recv()).await,
+        update(1.0)
+    );
+    // Polled a few more times at the same price, and without an answer for dogecoin,
+    // the next update is the change
+    sleep(Duration::from_millis(50)).await;
+    set_price(2.0);
+    assert_eq!(
+        timeout(Duration::from_secs(2), updates.recv()).await,
+        update(2.0)
+    );
+
+    // Unwatched coins aren't polled
+    feed.unwatch("bitcoin");
+    sleep(Duration::from_millis(30)).await;
+    set_price(3.0);
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(updates.try_recv(), Err(TryRecvError::Empty));
+
+    // Watched again, its current price goes out even if it's the last one sent
+    set_price(2.0);
+    feed.watch("bitcoin");
+    assert_eq!(
+        timeout(Duration::from_secs(2), updates.recv()).await,
+        update(2.0)
+    );
+    shutdown.cancel();
+}
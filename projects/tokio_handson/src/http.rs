@@ -0,0 +1,362 @@
+use std::{fmt, io};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Longest request line plus headers accepted, in bytes.
+pub const MAX_HEAD: usize = 8 * 1024;
+/// Largest request body accepted, in bytes.
+pub const MAX_BODY: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// Without the query string.
+    pub path: String,
+    // Only the upstream stub has routes with a query so far
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The first query parameter named `name`, as is, without percent-decoding.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether the client wants the connection kept open after the response.
+    pub fn keep_alive(&self) -> bool {
+        match self.header("connection") {
+            Some(value) if value.eq_ignore_ascii_case("close") => false,
+            Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+            _ => self.version == "HTTP/1.1",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    Io(io::Error),
+    BadRequest(&'static str),
+    HeadTooLarge,
+    BodyTooLarge,
+    NotImplemented(&'static str),
+}
+
+impl HttpError {
+    /// What to tell the client before closing the connection.
+    pub fn response(&self) -> Response {
+        let status = match self {
+            HttpError::Io(_) | HttpError::BadRequest(_) => 400,
+            HttpError::HeadTooLarge => 431,
+            HttpError::BodyTooLarge => 413,
+            HttpError::NotImplemented(_) => 501,
+        };
+        Response::text(status, self.to_string())
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Io(e) => write!(f, "I/O error: {e}"),
+            HttpError::BadRequest(reason) => write!(f, "Bad request: {reason}"),
+            HttpError::HeadTooLarge => write!(f, "Request headers are too large"),
+            HttpError::BodyTooLarge => write!(f, "Request body is too large"),
+            HttpError::NotImplemented(what) => write!(f, "Not implemented: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+/// Reads the next request, however its bytes are split across reads. `None` if the
+/// connection is closed before one starts.
+pub async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Request>, HttpError> {
+    let mut head_len = 0;
+    let mut line = Vec::new();
+
+    // Blank lines before the request line are allowed
+    loop {
+        line.clear();
+        if read_line(reader, &mut line, &mut head_len).await? == 0 {
+            return Ok(None);
+        }
+        if !trim_line(&line).is_empty() {
+            break;
+        }
+    }
+    let request_line = std::str::from_utf8(trim_line(&line))
+        .map_err(|_| HttpError::BadRequest("request line is not UTF-8"))?
+        .to_string();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(HttpError::BadRequest("malformed request line"));
+    };
+    if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(HttpError::BadRequest("malformed method"));
+    }
+    if !target.starts_with('/') {
+        return Err(HttpError::BadRequest("malformed request target"));
+    }
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return Err(HttpError::NotImplemented("HTTP version"));
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if read_line(reader, &mut line, &mut head_len).await? == 0 {
+            return Err(HttpError::BadRequest("incomplete headers"));
+        }
+        let header = trim_line(&line);
+        if header.is_empty() {
+            break;
+        }
+        let header = std::str::from_utf8(header)
+            .map_err(|_| HttpError::BadRequest("header is not UTF-8"))?;
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(HttpError::BadRequest("malformed header"));
+        };
+        if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+            return Err(HttpError::BadRequest("malformed header name"));
+        }
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+    if request.header("transfer-encoding").is_some() {
+        return Err(HttpError::NotImplemented("transfer-encoding"));
+    }
+    if let Some(len) = request.header("content-length") {
+        // parse() would take a leading '+', which other parsers on the path may not
+        let len = Some(len)
+            .filter(|len| !len.is_empty() && len.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or(HttpError::BadRequest("malformed content-length"))?;
+        if len > MAX_BODY {
+            return Err(HttpError::BodyTooLarge);
+        }
+        request.body = vec![0; len];
+        reader
+            .read_exact(&mut request.body)
+            .await
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => HttpError::BadRequest("incomplete body"),
+                _ => HttpError::Io(e),
+            })?;
+    }
+    Ok(Some(request))
+}
+
+/// Reads up to and including the next `\n`, counting it against `MAX_HEAD`. Returns
+/// 0 at a clean end of the stream.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    head_len: &mut usize,
+) -> Result<usize, HttpError> {
+    let budget = (MAX_HEAD - *head_len) as u64;
+    let read = (&mut *reader).take(budget).read_until(b'\n', line).await?;
+    *head_len += read;
+
+    if read > 0 && !line.ends_with(b"\n") {
+        return Err(if *head_len >= MAX_HEAD {
+            HttpError::HeadTooLarge
+        } else {
+            HttpError::BadRequest("incomplete request")
+        });
+    }
+    if read == 0 && *head_len >= MAX_HEAD {
+        return Err(HttpError::HeadTooLarge);
+    }
+    Ok(read)
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body.to_string())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Writes the response, adding `Content-Length` and `Connection`, or only
+    /// `Connection: Upgrade` when switching protocols.
+    pub async fn write_to<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        keep_alive: bool,
+    ) -> io::Result<()> {
+        let mut out = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            out.push_str(&format!("{name}: {value}\r\n"));
+        }
+        let connection = if self.status == 101 {
+            "Upgrade"
+        } else {
+            out.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+            if keep_alive {
+                "keep-alive"
+            } else {
+                "close"
+            }
+        };
+        out.push_str(&format!("Connection: {connection}\r\n\r\n"));
+
+        let mut bytes = out.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        writer.write_all(&bytes).await?;
+        writer.flush().await
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        426 => "Upgrade Required",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+#[tokio::test]
+async fn parse_across_reads() {
+    use std::time::Duration;
+    use tokio::io::BufReader;
+
+    let (mut client, server) = tokio::io::duplex(64);
+    tokio::spawn(async move {
+        for chunk in [
+            &b"\r\nPOST /submit?x=1 HT"[..],
+            b"TP/1.1\r\nHost: example\r\nContent-Le",
+            b"ngth: 11\r\nX-Empty:\r\n\r\nhello",
+            b" worldGET / HTTP/1.0\r\n\r\n",
+        ] {
+            client.write_all(chunk).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    });
+
+    let mut reader = BufReader::new(server);
+    let request = read_request(&mut reader).await.unwrap().unwrap();
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/submit");
+    assert_eq!(request.query_param("x"), Some("1"));
+    assert_eq!(request.query_param("y"), None);
+    assert_eq!(request.header("HOST"), Some("example"));
+    assert_eq!(request.header("x-empty"), Some(""));
+    assert_eq!(request.body, b"hello world");
+    assert!(request.keep_alive());
+
+    let request = read_request(&mut reader).await.unwrap().unwrap();
+    assert_eq!(
+        (request.method.as_str(), request.path.as_str()),
+        ("GET", "/")
+    );
+    assert!(!request.keep_alive());
+    assert!(read_request(&mut reader).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn malformed_requests() {
+    for (input, status) in [
+        (&b"GET /\r\n\r\n"[..], 400),
+        (b"get / HTTP/1.1\r\n\r\n", 400),
+        (b"GET / HTTP/2.0\r\n\r\n", 501),
+        (b"GET / HTTP/1.1\r\nNo colon\r\n\r\n", 400),
+        (b"GET / HTTP/1.1\r\nHost: x\r\n", 400),
+        (b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort", 400),
+        (b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello", 400),
+        (b"POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n", 413),
+        (
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            501,
+        ),
+        (&[b'a'; MAX_HEAD + 1][..], 431),
+    ] {
+        let error = read_request(&mut &input[..]).await.unwrap_err();
+        assert_eq!(error.response().status, status, "{error}");
+    }
+}
@@ -1,7 +1,18 @@
-use tokio::task::JoinSet;
-use tracing::error;
+use std::time::Duration;
+use supervisor::{Policy, Restart, Supervisor};
+use tokio::signal;
+use tracing::info;
 mod api;
+mod breaker;
+mod cache;
+mod feed;
+mod http;
+mod price;
+#[cfg(test)]
+mod stub;
+mod supervisor;
 mod tester;
+mod ws;
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
@@ -10,17 +21,27 @@ async fn main() -> eyre::Result<()> {
         .with_max_level(tracing::Level::DEBUG)
         .init();
 
-    let mut join_set = JoinSet::new();
-    join_set.spawn(api::run());
-    join_set.spawn(tester::run());
+    let mut supervisor = Supervisor::new();
+    supervisor.add("api", Policy::default(), api::run);
+    // Meant to fail, so it's let fail more often before it's given up on
+    supervisor.add(
+        "tester",
+        Policy {
+            restart: Restart::Always,
+            max_backoff: Duration::from_secs(10),
+            max_restarts: 20,
+            ..Policy::default()
+        },
+        tester::run,
+    );
 
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(_)) => (),
-            Ok(Err(err)) => error!("Task Error: {}", err),
-            Err(e) => error!("{e:?}"),
+    let shutdown = supervisor.shutdown_token();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            info!("Ctrl-C received, shutting down");
+            shutdown.cancel();
         }
-    }
+    });
 
-    eyre::Ok(())
+    supervisor.run().await
 }
@@ -0,0 +1,228 @@
+use rand::Rng;
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+use tokio::time::sleep;
+use tracing::debug;
+
+/// CoinGecko's public API.
+pub const DEFAULT_BASE_URL: &str = "https://api.coingecko.com/api/v3";
+
+/// Cheap to clone, so one failed request can be reported to everyone waiting on it.
+#[derive(Debug, Clone)]
+pub enum PriceError {
+    Request(Arc<reqwest::Error>),
+    /// No complete answer within `FetchConfig::timeout`.
+    Timeout,
+    /// The upstream answered with a status other than 200.
+    Status(u16),
+    /// The upstream has no price for this pair.
+    Unknown {
+        coin: String,
+        currency: String,
+    },
+    /// Not asked, as it failed too often lately.
+    CircuitOpen {
+        retry_after: Duration,
+    },
+}
+
+impl PriceError {
+    /// Whether asking again might go better.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            PriceError::Request(e) => e.is_connect(),
+            PriceError::Timeout => true,
+            PriceError::Status(status) => *status >= 500,
+            PriceError::Unknown { .. } | PriceError::CircuitOpen { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for PriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceError::Request(e) => write!(f, "upstream request failed: {e}"),
+            PriceError::Timeout => write!(f, "upstream request timed out"),
+            PriceError::Status(status) => write!(f, "upstream answered with status {status}"),
+            PriceError::Unknown { coin, currency } => {
+                write!(f, "no price for {coin} in {currency}")
+            }
+            PriceError::CircuitOpen { retry_after } => {
+                write!(f, "upstream failing, not asked again for {retry_after:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PriceError {}
+
+impl From<reqwest::Error> for PriceError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            PriceError::Timeout
+        } else {
+            PriceError::Request(Arc::new(e))
+        }
+    }
+}
+
+/// Where prices come from.
+pub trait PriceProvider: Send + Sync + 'static {
+    /// The price of one `coin`, a CoinGecko id like `bitcoin`, in `currency`, like `usd`.
+    fn price(
+        &self,
+        coin: &str,
+        currency: &str,
+    ) -> impl Future<Output = Result<f64, PriceError>> + Send;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FetchConfig {
+    /// For each attempt, from connecting to the end of the body.
+    pub timeout: Duration,
+    /// Attempts after the first, for transient errors only.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for each one after it.
+    pub initial_backoff: Duration,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            initial_backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Prices from CoinGecko's `/simple/price`, or anything answering like it.
+pub struct HttpPriceProvider {
+    client: reqwest::Client,
+    base_url: String,
+    config: FetchConfig,
+}
+
+impl HttpPriceProvider {
+    /// `base_url` is everything before `/simple/price`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_config(base_url, FetchConfig::default())
+    }
+
+    pub fn with_config(base_url: impl Into<String>, config: FetchConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            config,
+        }
+    }
+
+    async fn fetch(&self, coin: &str, currency: &str) -> Result<f64, PriceError> {
+        let url = format!("{}/simple/price", self.base_url);
+        let response = self
+            .client
+            .get(url)
+            .query(&[("ids", coin), ("vs_currencies", currency)])
+            .timeout(self.config.timeout)
+            .send()
+            .await?;
+        if response.status() != reqwest::StatusCode::OK {
+            return Err(PriceError::Status(response.status().as_u16()));
+        }
+
+        // Shaped like {"bitcoin": {"usd": 97000.5}}, leaving out what it doesn't know
+        let data = response.json::<serde_json::Value>().await?;
+        data[coin][currency]
+            .as_f64()
+            .ok_or_else(|| PriceError::Unknown {
+                coin: coin.to_string(),
+                currency: currency.to_string(),
+            })
+    }
+}
+
+impl PriceProvider for HttpPriceProvider {
+    async fn price(&self, coin: &str, currency: &str) -> Result<f64, PriceError> {
+        let mut backoff = self.config.initial_backoff;
+        for _ in 0..self.config.retries {
+            match self.fetch(coin, currency).await {
+                Err(e) if e.is_transient() => {
+                    debug!("Retrying the price of {coin} in {currency} in {backoff:?}: {e}");
+                    // Jittered, so retries from different requests spread out
+                    let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+                    sleep(backoff.mul_f64(jitter)).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+        self.fetch(coin, currency).await
+    }
+}
+
+#[tokio::test]
+async fn http_provider_against_stub() {
+    use crate::stub::StubUpstream;
+
+    let stub =
+        StubUpstream::start(&[("bitcoin", "usd", 97000.5), ("ethereum", "eur", 3100.0)]).await;
+    let provider = HttpPriceProvider::new(format!("{}/", stub.base_url()));
+
+    assert_eq!(provider.price("bitcoin", "usd").await.unwrap(), 97000.5);
+    assert_eq!(provider.price("ethereum", "eur").await.unwrap(), 3100.0);
+    assert!(matches!(
+        provider.price("ethereum", "usd").await,
+        Err(PriceError::Unknown { .. })
+    ));
+    assert!(matches!(
+        provider.price("dogecoin", "usd").await,
+        Err(PriceError::Unknown { .. })
+    ));
+
+    let wrong_base = HttpPriceProvider::new(format!("{}/v2", stub.base_url()));
+    assert!(matches!(
+        wrong_base.price("bitcoin", "usd").await,
+        Err(PriceError::Status(404))
+    ));
+}
+
+#[tokio::test]
+async fn retries_and_timeouts() {
+    use crate::stub::{Fault, StubUpstream};
+
+    let stub = StubUpstream::start(&[("bitcoin", "usd", 97000.5)]).await;
+    let provider = HttpPriceProvider::with_config(
+        stub.base_url(),
+        FetchConfig {
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            initial_backoff: Duration::from_millis(10),
+        },
+    );
+
+    // Server errors and slow answers are retried
+    stub.inject([Fault::Status(503), Fault::Delay(Duration::from_millis(300))]);
+    assert_eq!(provider.price("bitcoin", "usd").await.unwrap(), 97000.5);
+    assert_eq!(stub.requests(), 3);
+
+    // Up to a point
+    stub.inject([Fault::Status(500); 3]);
+    assert!(matches!(
+        provider.price("bitcoin", "usd").await,
+        Err(PriceError::Status(500))
+    ));
+    assert_eq!(stub.requests(), 6);
+    stub.inject([Fault::Delay(Duration::from_millis(300)); 3]);
+    assert!(matches!(
+        provider.price("bitcoin", "usd").await,
+        Err(PriceError::Timeout)
+    ));
+    assert_eq!(stub.requests(), 9);
+
+    // Other errors are final
+    stub.inject([Fault::Status(429)]);
+    assert!(matches!(
+        provider.price("bitcoin", "usd").await,
+        Err(PriceError::Status(429))
+    ));
+    assert_eq!(stub.requests(), 10);
+}
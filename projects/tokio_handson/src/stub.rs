@@ -0,0 +1,127 @@
+//! A stand-in for the upstream price API, served from loopback for tests.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    time::sleep,
+};
+
+use crate::http::{read_request, Request, Response};
+
+/// Answers `GET /simple/price?ids=<coin>&vs_currencies=<currency>` like CoinGecko
+/// does, from a set of prices, unless told to misbehave.
+pub struct StubUpstream {
+    addr: SocketAddr,
+    state: Arc<State>,
+}
+
+/// What to do to the next request instead of answering it right away.
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    Status(u16),
+    /// Answer, but only after this long.
+    Delay(Duration),
+}
+
+type Table = HashMap<String, HashMap<String, f64>>;
+
+#[derive(Default)]
+struct State {
+    table: Mutex<Table>,
+    faults: Mutex<VecDeque<Fault>>,
+    requests: AtomicUsize,
+}
+
+impl StubUpstream {
+    pub async fn start(prices: &[(&str, &str, f64)]) -> Self {
+        let state = Arc::new(State::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let serving = Arc::clone(&state);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, Arc::clone(&serving)));
+            }
+        });
+
+        let stub = Self { addr, state };
+        for (coin, currency, price) in prices {
+            stub.set_price(coin, currency, *price);
+        }
+        stub
+    }
+
+    pub fn set_price(&self, coin: &str, currency: &str, price: f64) {
+        self.state
+            .table
+            .lock()
+            .unwrap()
+            .entry(coin.to_string())
+            .or_default()
+            .insert(currency.to_string(), price);
+    }
+
+    /// The next requests get these faults, one each.
+    pub fn inject(&self, faults: impl IntoIterator<Item = Fault>) {
+        self.state.faults.lock().unwrap().extend(faults);
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> usize {
+        self.state.requests.load(Ordering::SeqCst)
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<State>) {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    while let Ok(Some(request)) = read_request(&mut reader).await {
+        state.requests.fetch_add(1, Ordering::SeqCst);
+        let fault = state.faults.lock().unwrap().pop_front();
+        let response = match fault {
+            Some(Fault::Status(status)) => Response::text(status, "Injected fault"),
+            Some(Fault::Delay(delay)) => {
+                sleep(delay).await;
+                respond(&request, &state.table.lock().unwrap())
+            }
+            None => respond(&request, &state.table.lock().unwrap()),
+        };
+        if response.write_to(&mut writer, true).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn respond(request: &Request, table: &Table) -> Response {
+    if request.path != "/simple/price" {
+        return Response::text(404, "Not Found");
+    }
+    let (Some(coin), Some(currency)) = (
+        request.query_param("ids"),
+        request.query_param("vs_currencies"),
+    ) else {
+        return Response::text(400, "missing ids or vs_currencies");
+    };
+
+    let mut body = serde_json::json!({});
+    if let Some(prices) = table.get(coin) {
+        body[coin] = serde_json::json!({});
+        if let Some(price) = prices.get(currency) {
+            body[coin][currency] = serde_json::json!(price);
+        }
+    }
+    Response::json(200, &body)
+}
@@ -0,0 +1,469 @@
+use eyre::bail;
+use rand::Rng;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// When a task that ended is started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    #[cfg_attr(not(test), allow(dead_code))]
+    Never,
+    Always,
+    /// After an error or a panic, not after it returned `Ok`.
+    OnFailure,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub restart: Restart,
+    /// The delay before the first restart, doubled for every restart still in the window.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Past this many restarts within `window`, the task is given up on.
+    pub max_restarts: usize,
+    pub window: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            restart: Restart::OnFailure,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Policy {
+    /// Exponential, with up to half of it taken off at random so tasks that failed
+    /// together don't all come back at the same time.
+    fn backoff(&self, recent_restarts: usize) -> Duration {
+        let exponent = recent_restarts.min(31) as u32;
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exit {
+    Completed,
+    Failed(String),
+    Panicked(String),
+}
+
+/// What happened to a supervised task, also logged as it happens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Started {
+        task: &'static str,
+        attempt: u32,
+    },
+    Exited {
+        task: &'static str,
+        attempt: u32,
+        exit: Exit,
+    },
+    Restarting {
+        task: &'static str,
+        attempt: u32,
+        delay: Duration,
+    },
+    /// Restarted too often within the policy's window.
+    GaveUp {
+        task: &'static str,
+        restarts: usize,
+    },
+}
+
+type Supervised = Pin<Box<dyn Future<Output = Option<&'static str>> + Send>>;
+
+/// Runs named tasks, restarting them as their policies say until shutdown.
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Vec<Supervised>,
+    events: Option<mpsc::UnboundedSender<Event>>,
+    shutdown: CancellationToken,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events of the tasks added from now on.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<Event> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.events = Some(sender);
+        receiver
+    }
+
+    /// Cancelling it shuts every task down: they're handed the token and aren't
+    /// restarted once it's cancelled.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// `start` is called for every attempt, and should return soon after the token
+    /// it's given is cancelled.
+    pub fn add<F, Fut>(&mut self, name: &'static str, policy: Policy, start: F) -> &mut Self
+    where
+        F: Fn(CancellationToken) -> Fut + Send + 'static,
+        Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        let events = self.events.clone();
+        let shutdown = self.shutdown.clone();
+        self.tasks
+            .push(Box::pin(supervise(name, policy, start, events, shutdown)));
+        self
+    }
+
+    /// Until every task has ended for good. An error names the tasks given up on.
+    pub async fn run(self) -> eyre::Result<()> {
+        let mut join_set = JoinSet::new();
+        for task in self.tasks {
+            join_set.spawn(task);
+        }
+
+        let mut gave_up = Vec::new();
+        while let Some(result) = join_set.join_next().await {
+            if let Some(name) = result? {
+                gave_up.push(name);
+            }
+        }
+        if !gave_up.is_empty() {
+            gave_up.sort_unstable();
+            bail!("gave up on {}", gave_up.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// Aborts the attempt if the supervisor itself is dropped.
+struct Attempt(JoinHandle<eyre::Result<()>>);
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Returns the name of the task if it was given up on.
+async fn supervise<F, Fut>(
+    task: &'static str,
+    policy: Policy,
+    start: F,
+    events: Option<mpsc::UnboundedSender<Event>>,
+    shutdown: CancellationToken,
+) -> Option<&'static str>
+where
+    F: Fn(CancellationToken) -> Fut + Send + 'static,
+    Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+{
+    let emit = |event: Event| {
+        match &event {
+            Event::Started { task, attempt } => info!(task, attempt, "Task started"),
+            Event::Exited {
+                task,
+                attempt,
+                exit: Exit::Completed,
+            } => info!(task, attempt, "Task completed"),
+            Event::Exited {
+                task,
+                attempt,
+                exit: Exit::Failed(error) | Exit::Panicked(error),
+            } => warn!(task, attempt, error, "Task failed"),
+            Event::Restarting {
+                task,
+                attempt,
+                delay,
+            } => warn!(
+                task,
+                attempt,
+                delay_ms = delay.as_millis() as u64,
+                "Restarting task"
+            ),
+            Event::GaveUp { task, restarts } => {
+                error!(task, restarts, "Task restarted too often, giving up")
+            }
+        }
+        if let Some(events) = &events {
+            let _ = events.send(event);
+        }
+    };
+
+    let mut restarts = VecDeque::<Instant>::new();
+    for attempt in 1.. {
+        emit(Event::Started { task, attempt });
+        // Spawned, so a panic ends the attempt rather than the supervisor
+        let mut handle = Attempt(tokio::spawn(start(shutdown.clone())));
+        let exit = match (&mut handle.0).await {
+            Ok(Ok(())) => Exit::Completed,
+            Ok(Err(e)) => Exit::Failed(e.to_string()),
+            Err(e) => match e.try_into_panic() {
+                Ok(panic) => Exit::Panicked(
+                    panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "panicked".to_string()),
+                ),
+                Err(_) => Exit::Failed("cancelled".to_string()),
+            },
+        };
+        let restart = match policy.restart {
+            Restart::Never => false,
+            Restart::Always => true,
+            Restart::OnFailure => exit != Exit::Completed,
+        };
+        emit(Event::Exited {
+            task,
+            attempt,
+            exit,
+        });
+        if !restart || shutdown.is_cancelled() {
+            return None;
+        }
+
+        let now = Instant::now();
+        while restarts
+            .front()
+            .is_some_and(|&at| now.duration_since(at) > policy.window)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() >= policy.max_restarts {
+            emit(Event::GaveUp {
+                task,
+                restarts: restarts.len(),
+            });
+            return Some(task);
+        }
+
+        let delay = policy.backoff(restarts.len());
+        emit(Event::Restarting {
+            task,
+            attempt: attempt + 1,
+            delay,
+        });
+        tokio::select! {
+            _ = sleep(delay) => (),
+            _ = shutdown.cancelled() => return None,
+        }
+        restarts.push_back(Instant::now());
+    }
+    unreachable!("attempts run out")
+}
+
+#[tokio::test]
+async fn restart_policies() {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    let policy = Policy {
+        initial_backoff: Duration::from_millis(4),
+        max_backoff: Duration::from_millis(10),
+        max_restarts: 3,
+        window: Duration::from_secs(10),
+        ..Policy::default()
+    };
+
+    let mut supervisor = Supervisor::new();
+    let mut events = supervisor.subscribe();
+    supervisor
+        .add("failing", policy, |_| async { bail!("broken") })
+        .add(
+            "never",
+            Policy {
+                restart: Restart::Never,
+                ..policy
+            },
+            |_| async { bail!("broken") },
+        );
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&attempts);
+    supervisor.add("flaky", policy, move |_| {
+        let attempt = counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+            if attempt == 0 {
+                panic!("first try");
+            }
+            Ok(())
+        }
+    });
+    let runs = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&runs);
+    supervisor.add(
+        "always",
+        Policy {
+            restart: Restart::Always,
+            max_restarts: 2,
+            ..policy
+        },
+        move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Ok(()) }
+        },
+    );
+
+    let error = supervisor.run().await.unwrap_err();
+    assert_eq!(error.to_string(), "gave up on always, failing");
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+    let mut all = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        all.push(event);
+    }
+    let of = |name| {
+        all.iter()
+            .filter(|event| match event {
+                Event::Started { task, .. }
+                | Event::Exited { task, .. }
+                | Event::Restarting { task, .. }
+                | Event::GaveUp { task, .. } => *task == name,
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let failing = of("failing");
+    assert_eq!(failing.len(), 4 * 2 + 3 + 1);
+    assert_eq!(
+        failing.last(),
+        Some(&Event::GaveUp {
+            task: "failing",
+            restarts: 3
+        })
+    );
+    // Doubling up to the cap, with jitter taking off at most half
+    let delays = failing
+        .iter()
+        .filter_map(|event| match event {
+            Event::Restarting { delay, .. } => Some(delay.as_millis()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert!((2..=4).contains(&delays[0]), "{delays:?}");
+    assert!((4..=8).contains(&delays[1]), "{delays:?}");
+    assert!((5..=10).contains(&delays[2]), "{delays:?}");
+
+    assert_eq!(
+        of("never"),
+        [
+            Event::Started {
+                task: "never",
+                attempt: 1
+            },
+            Event::Exited {
+                task: "never",
+                attempt: 1,
+                exit: Exit::Failed("broken".to_string())
+            }
+        ]
+    );
+    let flaky = of("flaky");
+    assert_eq!(
+        flaky[1],
+        Event::Exited {
+            task: "flaky",
+            attempt: 1,
+            exit: Exit::Panicked("first try".to_string())
+        }
+    );
+    assert_eq!(
+        flaky.last(),
+        Some(&Event::Exited {
+            task: "flaky",
+            attempt: 2,
+            exit: Exit::Completed
+        })
+    );
+}
+
+#[tokio::test]
+async fn shutdown_ends_every_task() {
+    use crate::{
+        api,
+        cache::{CacheConfig, PriceCache},
+        feed::{FeedConfig, PriceFeed},
+        price::HttpPriceProvider,
+        stub::StubUpstream,
+        tester,
+    };
+    use tokio::{io::AsyncWriteExt, net::TcpListener, net::TcpStream, time::timeout};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let listener = std::sync::Mutex::new(Some(listener));
+    let provider = HttpPriceProvider::new(StubUpstream::start(&[]).await.base_url());
+    let prices = PriceCache::new(provider, CacheConfig::default());
+
+    let mut supervisor = Supervisor::new();
+    let mut events = supervisor.subscribe();
+    supervisor
+        .add("api", Policy::default(), move |shutdown| {
+            let listener = listener.lock().unwrap().take().unwrap();
+            let feed = PriceFeed::start(prices.clone(), FeedConfig::default(), shutdown.clone());
+            api::serve(listener, prices.clone(), feed, shutdown)
+        })
+        .add("tester", Policy::default(), tester::run)
+        // Shut down while waiting to be restarted
+        .add(
+            "backing off",
+            Policy {
+                restart: Restart::Always,
+                initial_backoff: Duration::from_secs(60),
+                ..Policy::default()
+            },
+            |_| async { Ok(()) },
+        );
+    let shutdown = supervisor.shutdown_token();
+    let run = tokio::spawn(supervisor.run());
+
+    // A connection left open between requests
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    idle.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    shutdown.cancel();
+    timeout(Duration::from_secs(2), run)
+        .await
+        .expect("every task ends")
+        .unwrap()
+        .unwrap();
+
+    let mut ended = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let Event::Exited { task, exit, .. } = event {
+            ended.push((task, exit));
+        }
+    }
+    ended.sort_unstable_by_key(|(task, _)| *task);
+    assert_eq!(
+        ended,
+        [
+            ("api", Exit::Completed),
+            ("backing off", Exit::Completed),
+            ("tester", Exit::Completed)
+        ]
+    );
+}
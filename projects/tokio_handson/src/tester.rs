@@ -2,22 +2,19 @@ use eyre::bail;
 use rand::prelude::*;
 use std::time::Duration;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
-use tracing::{error, info};
+use tracing::info;
 
-pub async fn run() -> eyre::Result<()> {
-    loop {
-        if let Err(e) = main().await {
-            error!("{e}");
-        }
-    }
-}
-
-pub async fn main() -> eyre::Result<()> {
+/// Fails at random, for the supervisor to restart.
+pub async fn run(shutdown: CancellationToken) -> eyre::Result<()> {
     info!("Starting the Tester...");
     let mut rng = StdRng::from_entropy();
     loop {
-        sleep(Duration::from_secs(1)).await;
+        tokio::select! {
+            _ = sleep(Duration::from_secs(1)) => (),
+            _ = shutdown.cancelled() => return Ok(()),
+        }
         let random_number = rng.gen_range(0..10);
         if random_number >= 5 {
             bail!("This is a test error {random_number}");
@@ -0,0 +1,480 @@
+//! `GET /stream`, prices pushed over a WebSocket.
+//!
+//! Clients send `{"subscribe": ["bitcoin", ...]}` and `{"unsubscribe": [...]}`, and
+//! get `{"coin": "bitcoin", "currency": "usd", "price": 97000.5}` for each coin they
+//! subscribe to, right away and then whenever its price changes. A client too slow
+//! to keep up gets `{"lagged": <updates missed>}` followed by the current prices.
+//! Past `FeedConfig::max_subscriptions` coins, subscribing gets an error instead.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::{
+    net::TcpStream,
+    sync::broadcast::error::RecvError,
+    time::{interval_at, Instant},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::derive_accept_key,
+        protocol::{frame::coding::CloseCode, CloseFrame, Role},
+        Message,
+    },
+    WebSocketStream,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::{
+    api::is_valid_id,
+    cache::PriceCache,
+    feed::{PriceFeed, PriceUpdate, CURRENCY},
+    http::{Request, Response},
+    price::PriceProvider,
+};
+
+/// Whether `request` asks to switch to WebSocket.
+pub fn is_upgrade(request: &Request) -> bool {
+    request
+        .header("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// The `101 Switching Protocols` answering a valid handshake, else why it isn't one.
+pub fn handshake(request: &Request) -> Result<Response, &'static str> {
+    let upgrades = request.header("connection").is_some_and(|value| {
+        value
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    if request.method != "GET" || !upgrades {
+        return Err("not a WebSocket handshake");
+    }
+    if request.header("sec-websocket-version") != Some("13") {
+        return Err("unsupported WebSocket version");
+    }
+    let Some(key) = request.header("sec-websocket-key") else {
+        return Err("missing Sec-WebSocket-Key");
+    };
+
+    Ok(Response::new(101)
+        .with_header("Upgrade", "websocket")
+        .with_header("Sec-WebSocket-Accept", &derive_accept_key(key.as_bytes())))
+}
+
+/// Serves one client after the handshake, until either side closes or shutdown.
+/// `read_ahead` is whatever was read past the handshake.
+pub async fn session<P: PriceProvider>(
+    stream: TcpStream,
+    read_ahead: Vec<u8>,
+    prices: &PriceCache<P>,
+    feed: &PriceFeed,
+    shutdown: &CancellationToken,
+) -> eyre::Result<()> {
+    let mut ws = WebSocketStream::from_partially_read(stream, read_ahead, Role::Server, None).await;
+    let mut updates = feed.subscribe();
+    let mut subscribed = Subscribed {
+        feed,
+        sent: HashMap::new(),
+    };
+    let every = feed.config().ping_interval;
+    let mut pings = interval_at(Instant::now() + every, every);
+    let mut awaiting_pong = false;
+
+    loop {
+        tokio::select! {
+            message = ws.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    for reply in subscribed.command(&text, prices).await {
+                        ws.send(Message::text(reply.to_string())).await?;
+                    }
+                }
+                Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                Some(Ok(Message::Binary(_))) => {
+                    let reply = json!({ "error": "commands are JSON text messages" });
+                    ws.send(Message::text(reply.to_string())).await?;
+                }
+                // Pings are answered and closes completed by the next read
+                Some(Ok(_)) => (),
+                Some(Err(e)) => return Err(e.into()),
+                None => {
+                    debug!("WebSocket Closed");
+                    return Ok(());
+                }
+            },
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    if let Some(message) = subscribed.update(&update) {
+                        ws.send(Message::text(message.to_string())).await?;
+                    }
+                }
+                // Rather than the updates still queued, which may be stale by now
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("WebSocket Client Lagged by {missed} Updates");
+                    let lagged = json!({ "lagged": missed });
+                    ws.send(Message::text(lagged.to_string())).await?;
+                    let coins = subscribed.sent.keys().cloned().collect::<Vec<_>>();
+                    for coin in coins {
+                        let price = subscribed.current(prices, &coin).await;
+                        ws.send(Message::text(price.to_string())).await?;
+                    }
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = pings.tick() => {
+                if awaiting_pong {
+                    debug!("WebSocket Client Unresponsive, Closing");
+                    return Ok(());
+                }
+                ws.send(Message::Ping(Default::default())).await?;
+                awaiting_pong = true;
+            }
+            _ = shutdown.cancelled() => {
+                let frame = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "shutting down".into(),
+                };
+                ws.send(Message::Close(Some(frame))).await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A client's coins, watched on the feed for as long as it's subscribed to them.
+struct Subscribed<'a> {
+    feed: &'a PriceFeed,
+    /// The last price sent for each coin, so none is sent twice in a row.
+    sent: HashMap<String, Option<f64>>,
+}
+
+impl Subscribed<'_> {
+    /// Runs a command, returning the messages answering it.
+    async fn command<P: PriceProvider>(
+        &mut self,
+        text: &str,
+        prices: &PriceCache<P>,
+    ) -> Vec<Value> {
+        let command = serde_json::from_str::<Value>(text).unwrap_or_default();
+        let (subscribe, coins) = match (&command["subscribe"], &command["unsubscribe"]) {
+            (Value::Array(coins), Value::Null) => (true, coins),
+            (Value::Null, Value::Array(coins)) => (false, coins),
+            _ => {
+                return vec![json!({
+                    "error": r#"expected {"subscribe": [coins]} or {"unsubscribe": [coins]}"#
+                })]
+            }
+        };
+
+        let mut replies = Vec::new();
+        for coin in coins {
+            let Some(coin) = coin.as_str().filter(|coin| is_valid_id(coin)) else {
+                replies.push(json!({ "error": format!("not a coin id: {coin}") }));
+                continue;
+            };
+            if !subscribe {
+                if self.sent.remove(coin).is_some() {
+                    self.feed.unwatch(coin);
+                }
+            } else if self.sent.contains_key(coin) {
+                continue;
+            } else if self.sent.len() >= self.feed.config().max_subscriptions {
+                replies.push(json!({
+                    "coin": coin,
+                    "error": format!(
+                        "already subscribed to the most coins allowed, {}",
+                        self.sent.len()
+                    )
+                }));
+            } else {
+                self.sent.insert(coin.to_string(), None);
+                self.feed.watch(coin);
+                replies.push(self.current(prices, coin).await);
+            }
+        }
+        replies
+    }
+
+    /// The message for an update from the feed, unless it's of no interest.
+    fn update(&mut self, update: &PriceUpdate) -> Option<Value> {
+        let sent = self.sent.get_mut(&update.coin)?;
+        if *sent == Some(update.price) {
+            return None;
+        }
+        *sent = Some(update.price);
+        Some(message(&update.coin, update.price))
+    }
+
+    /// The price of `coin` now, or why there's none.
+    async fn current<P: PriceProvider>(&mut self, prices: &PriceCache<P>, coin: &str) -> Value {
+        match prices.price(coin, CURRENCY).await {
+            Ok(price) => {
+                self.sent.insert(coin.to_string(), Some(price));
+                message(coin, price)
+            }
+            Err(e) => json!({ "coin": coin, "error": e.to_string() }),
+        }
+    }
+}
+
+impl Drop for Subscribed<'_> {
+    fn drop(&mut self) {
+        for coin in self.sent.keys() {
+            self.feed.unwatch(coin);
+        }
+    }
+}
+
+fn message(coin: &str, price: f64) -> Value {
+    json!({ "coin": coin, "currency": CURRENCY, "price": price })
+}
+
+#[cfg(test)]
+async fn send(ws: &mut WebSocketStream<TcpStream>, command: Value) {
+    ws.send(Message::text(command.to_string())).await.unwrap();
+}
+
+#[cfg(test)]
+async fn next(ws: &mut WebSocketStream<TcpStream>) -> Message {
+    tokio::time::timeout(std::time::Duration::from_secs(2), ws.next())
+        .await
+        .expect("a message in time")
+        .unwrap()
+        .unwrap()
+}
+
+#[cfg(test)]
+async fn next_json(ws: &mut WebSocketStream<TcpStream>) -> Value {
+    match next(ws).await {
+        Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        message => panic!("not JSON: {message:?}"),
+    }
+}
+
+/// Prices from a stub upstream, cached for less than the 50 ms between polls so
+/// every poll sees the stub's prices.
+#[cfg(test)]
+async fn stub_prices() -> (
+    crate::stub::StubUpstream,
+    PriceCache<crate::price::HttpPriceProvider>,
+) {
+    use crate::{cache::CacheConfig, price::HttpPriceProvider, stub::StubUpstream};
+    use std::time::Duration;
+
+    let stub = StubUpstream::start(&[("bitcoin", "usd", 100.0), ("ethereum", "usd", 10.0)]).await;
+    let prices = PriceCache::new(
+        HttpPriceProvider::new(stub.base_url()),
+        CacheConfig {
+            ttl: Duration::from_millis(20),
+            stale_while_revalidate: Duration::ZERO,
+            stale_if_error: Duration::ZERO,
+        },
+    );
+    (stub, prices)
+}
+
+#[tokio::test]
+async fn streams_subscribed_prices() {
+    use crate::api::spawn_serve;
+    use std::time::Duration;
+    use tokio::{io::AsyncReadExt, io::AsyncWriteExt};
+    use tokio_tungstenite::client_async;
+
+    let (stub, prices) = stub_prices().await;
+    let shutdown = CancellationToken::new();
+    let (addr, _) = spawn_serve(prices, shutdown.clone()).await;
+
+    // Only as a WebSocket
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /stream HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut ws, _) = client_async(format!("ws://{addr}/stream"), stream)
+        .await
+        .unwrap();
+
+    send(&mut ws, json!({ "subscribe": ["bitcoin", "Bit coin"] })).await;
+    send(&mut ws, json!({ "listen": true })).await;
+    ws.send(Message::Ping("still there?".into())).await.unwrap();
+
+    // The current price right away, then changes as they're polled
+    assert_eq!(
+        next_json(&mut ws).await,
+        json!({ "coin": "bitcoin", "currency": "usd", "price": 100.0 })
+    );
+    assert_eq!(
+        next_json(&mut ws).await,
+        json!({ "error": r#"not a coin id: "Bit coin""# })
+    );
+    assert!(next_json(&mut ws).await["error"].is_string());
+    assert_eq!(next(&mut ws).await, Message::Pong("still there?".into()));
+    stub.set_price("bitcoin", "usd", 101.0);
+    assert_eq!(next_json(&mut ws).await["price"], 101.0);
+
+    // Nothing for coins unsubscribed from
+    send(
+        &mut ws,
+        json!({ "subscribe": ["ethereum"], "unsubscribe": [] }),
+    )
+    .await;
+    assert!(next_json(&mut ws).await["error"].is_string());
+    send(&mut ws, json!({ "subscribe": ["ethereum"] })).await;
+    assert_eq!(next_json(&mut ws).await["price"], 10.0);
+    send(&mut ws, json!({ "unsubscribe": ["bitcoin"] })).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    stub.set_price("bitcoin", "usd", 102.0);
+    stub.set_price("ethereum", "usd", 11.0);
+    assert_eq!(
+        next_json(&mut ws).await,
+        json!({ "coin": "ethereum", "currency": "usd", "price": 11.0 })
+    );
+
+    // Closed by the server on shutdown
+    shutdown.cancel();
+    match next(&mut ws).await {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+        message => panic!("not closed: {message:?}"),
+    }
+}
+
+#[tokio::test]
+async fn lagging_client_gets_current_prices() {
+    use crate::{
+        api::spawn_serve_with,
+        feed::{FeedConfig, PriceFeed},
+    };
+    use std::time::Duration;
+    use tokio_tungstenite::client_async;
+
+    let (_stub, prices) = stub_prices().await;
+    let shutdown = CancellationToken::new();
+    // Never polled, the updates are published by hand
+    let config = FeedConfig {
+        every: Duration::from_secs(3600),
+        capacity: 1,
+        ..FeedConfig::default()
+    };
+    let feed = PriceFeed::start(prices.clone(), config, shutdown.clone());
+    let (addr, _) = spawn_serve_with(prices, feed.clone(), shutdown.clone()).await;
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut ws, _) = client_async(format!("ws://{addr}/stream"), stream)
+        .await
+        .unwrap();
+    send(&mut ws, json!({ "subscribe": ["bitcoin"] })).await;
+    assert_eq!(next_json(&mut ws).await["price"], 100.0);
+
+    // All at once, with room for one, while the session waits on this task
+    for price in [1.0, 2.0, 3.0] {
+        feed.publish("bitcoin", price);
+    }
+    assert_eq!(next_json(&mut ws).await, json!({ "lagged": 2 }));
+    assert_eq!(
+        next_json(&mut ws).await,
+        json!({ "coin": "bitcoin", "currency": "usd", "price": 100.0 })
+    );
+    // Then the updates kept, when they differ from what was sent
+    assert_eq!(next_json(&mut ws).await["price"], 3.0);
+    shutdown.cancel();
+}
+
+#[tokio::test]
+async fn unanswered_pings_drop_the_client() {
+    use crate::{
+        api::spawn_serve_with,
+        feed::{FeedConfig, PriceFeed},
+    };
+    use std::time::Duration;
+    use tokio::time::{sleep, timeout};
+    use tokio_tungstenite::client_async;
+
+    let (_stub, prices) = stub_prices().await;
+    let shutdown = CancellationToken::new();
+    let config = FeedConfig {
+        ping_interval: Duration::from_millis(50),
+        ..FeedConfig::default()
+    };
+    let feed = PriceFeed::start(prices.clone(), config, shutdown.clone());
+    let (addr, _) = spawn_serve_with(prices, feed, shutdown.clone()).await;
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut ws, _) = client_async(format!("ws://{addr}/stream"), stream)
+        .await
+        .unwrap();
+
+    // Reading answers the pings, for several intervals the client stays
+    let mut pings = 0;
+    while pings < 4 {
+        match next(&mut ws).await {
+            Message::Ping(_) => pings += 1,
+            message => panic!("not a ping: {message:?}"),
+        }
+    }
+    send(&mut ws, json!({ "subscribe": ["ethereum"] })).await;
+    let mut replies = Vec::new();
+    while replies.is_empty() {
+        if let Message::Text(text) = next(&mut ws).await {
+            replies.push(serde_json::from_str::<Value>(&text).unwrap());
+        }
+    }
+    assert_eq!(replies[0]["price"], 10.0);
+
+    // Not reading leaves a ping unanswered, so the server hangs up
+    sleep(Duration::from_millis(150)).await;
+    let closed = timeout(Duration::from_secs(2), async {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Ping(_))) => continue,
+                Some(Ok(message)) => panic!("not closed: {message:?}"),
+                None | Some(Err(_)) => break,
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok());
+    shutdown.cancel();
+}
+
+#[tokio::test]
+async fn subscriptions_are_capped() {
+    use crate::{
+        api::spawn_serve_with,
+        feed::{FeedConfig, PriceFeed},
+    };
+    use tokio_tungstenite::client_async;
+
+    let (_stub, prices) = stub_prices().await;
+    let shutdown = CancellationToken::new();
+    let config = FeedConfig {
+        max_subscriptions: 1,
+        ..FeedConfig::default()
+    };
+    let feed = PriceFeed::start(prices.clone(), config, shutdown.clone());
+    let (addr, _) = spawn_serve_with(prices, feed, shutdown.clone()).await;
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut ws, _) = client_async(format!("ws://{addr}/stream"), stream)
+        .await
+        .unwrap();
+    send(
+        &mut ws,
+        json!({ "subscribe": ["bitcoin", "bitcoin", "ethereum"] }),
+    )
+    .await;
+    assert_eq!(next_json(&mut ws).await["price"], 100.0);
+    let refused = next_json(&mut ws).await;
+    assert_eq!(refused["coin"], "ethereum");
+    assert!(refused["error"].is_string());
+
+    // Room again once unsubscribed
+    send(&mut ws, json!({ "unsubscribe": ["bitcoin"] })).await;
+    send(&mut ws, json!({ "subscribe": ["ethereum"] })).await;
+    assert_eq!(next_json(&mut ws).await["price"], 10.0);
+    shutdown.cancel();
+}
@@ -148,6 +148,39 @@ impl<T> Iterator for Queue<T> {
     }
 }
 
+// Serialized from head to tail, the same order `pop` hands the data out
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for Queue<T>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let nodes = unsafe { std::iter::successors(self.head.as_ref(), |node| node.next.as_ref()) };
+        crate::serialize_seq(serializer, nodes.map(|node| &node.data))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for Queue<T>
+where
+    T: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let mut queue = Self::default();
+        for data in values {
+            queue.push(data);
+        }
+        Ok(queue)
+    }
+}
+
 #[test]
 fn fifth_list() {
     let binding = "HelloWorld".to_string();
@@ -176,3 +209,26 @@ fn fifth_list() {
         assert_eq!(list_data, *char)
     }
 }
+
+#[cfg(feature = "serde")]
+#[test]
+fn fifth_list_serde() {
+    let vec = "HelloWorld".chars().collect::<Vec<_>>();
+    let queue = Queue::<char>::from(vec.as_slice());
+
+    let json = serde_json::to_string(&queue).unwrap();
+    assert_eq!(json, r#"["H","e","l","l","o","W","o","r","l","d"]"#);
+    let from_json: Queue<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_json.iter().collect::<String>(), "HelloWorld");
+
+    let bytes = bincode::serialize(&queue).unwrap();
+    let mut from_bincode: Queue<char> = bincode::deserialize(&bytes).unwrap();
+    for c in "HelloWorld".chars() {
+        assert_eq!(from_bincode.pop(), Some(c));
+    }
+    assert_eq!(from_bincode.pop(), None);
+
+    let empty: Queue<char> =
+        bincode::deserialize(&bincode::serialize(&from_bincode).unwrap()).unwrap();
+    assert_eq!(empty.iter().count(), 0);
+}
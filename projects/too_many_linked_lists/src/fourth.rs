@@ -166,6 +166,58 @@ where
     }
 }
 
+// Serialized from front to back, the same order `pop_front` hands the data out
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for DoubleLinkList<T>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut nodes = Vec::new();
+        let mut current = self.head.clone();
+        while let Some(node) = current {
+            current = node.borrow().next.clone();
+            nodes.push(Data(node));
+        }
+        crate::serialize_seq(serializer, nodes)
+    }
+}
+
+/// A node's data, borrowed only while it's being serialized.
+#[cfg(feature = "serde")]
+struct Data<T>(Rc<RefCell<Node<T>>>);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for Data<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.borrow().data.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for DoubleLinkList<T>
+where
+    T: serde::Deserialize<'de> + Clone,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let mut list = Self::default();
+        for data in values {
+            list.push_back(data);
+        }
+        Ok(list)
+    }
+}
+
 #[test]
 fn fourth_list() {
     // If it's set to "HelloHello"
@@ -192,3 +244,22 @@ fn fourth_list() {
     assert_eq!(list.next(), Some('l'));
     assert_eq!(list.next_back(), Some('e'));
 }
+
+#[cfg(feature = "serde")]
+#[test]
+fn fourth_list_serde() {
+    let vec = "Hello".chars().collect::<Vec<_>>();
+    let list = DoubleLinkList::<char>::from(vec.as_slice());
+
+    let json = serde_json::to_string(&list).unwrap();
+    assert_eq!(json, r#"["o","l","l","e","H"]"#);
+    let from_json: DoubleLinkList<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_json.collect::<String>(), "olleH");
+
+    let bytes = bincode::serialize(&list).unwrap();
+    let mut from_bincode: DoubleLinkList<char> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(*from_bincode.peek_front().unwrap(), 'o');
+    assert_eq!(*from_bincode.peek_back().unwrap(), 'H');
+    assert_eq!(from_bincode.next_back(), Some('H'));
+    assert_eq!(from_bincode.collect::<String>(), "olle");
+}
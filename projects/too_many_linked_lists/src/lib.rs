@@ -7,3 +7,23 @@ pub mod first;
 pub mod fourth;
 pub mod second;
 pub mod third;
+
+/// Writes `items` as a sequence in one walk of the list. Formats like bincode need
+/// the length upfront, which a linked list only knows once it's been walked, so the
+/// items are gathered on the way.
+#[cfg(feature = "serde")]
+fn serialize_seq<S, I>(serializer: S, items: I) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    I: IntoIterator,
+    I::Item: serde::Serialize,
+{
+    use serde::ser::SerializeSeq;
+
+    let items = items.into_iter().collect::<Vec<_>>();
+    let mut seq = serializer.serialize_seq(Some(items.len()))?;
+    for item in &items {
+        seq.serialize_element(item)?;
+    }
+    seq.end()
+}
@@ -146,6 +146,40 @@ impl<'a, T> Iterator for ListIterMut<'a, T> {
     }
 }
 
+// Serialized from head to tail, the same order `pop` hands the data out
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for List<T>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let nodes = std::iter::successors(self.head.as_deref(), |node| node.next.as_deref());
+        crate::serialize_seq(serializer, nodes.map(|node| &node.data))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for List<T>
+where
+    T: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let mut list = Self::default();
+        // push() prepends, so the last element has to go in first
+        for data in values.into_iter().rev() {
+            list.push(data);
+        }
+        Ok(list)
+    }
+}
+
 #[test]
 fn second_list() {
     let binding = "Hello".to_string();
@@ -188,3 +222,24 @@ fn second_list() {
         assert_eq!(list_data, *char)
     }
 }
+
+#[cfg(feature = "serde")]
+#[test]
+fn second_list_serde() {
+    let mut list = List::<char>::default();
+    for c in "Hello".chars() {
+        list.push(c);
+    }
+
+    let json = serde_json::to_string(&list).unwrap();
+    assert_eq!(json, r#"["o","l","l","e","H"]"#);
+    let from_json: List<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_json.iter().collect::<String>(), "olleH");
+
+    let bytes = bincode::serialize(&list).unwrap();
+    let mut from_bincode: List<char> = bincode::deserialize(&bytes).unwrap();
+    for c in "olleH".chars() {
+        assert_eq!(from_bincode.pop(), Some(c));
+    }
+    assert_eq!(from_bincode.pop(), None);
+}
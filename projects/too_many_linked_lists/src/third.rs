@@ -99,6 +99,40 @@ where
     }
 }
 
+// Serialized from head to tail, the same order `iter` walks the list
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for PersistentList<T>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let nodes = std::iter::successors(self.head.as_deref(), |node| node.next.as_deref());
+        crate::serialize_seq(serializer, nodes.map(|node| &node.data))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for PersistentList<T>
+where
+    T: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let mut list = Self::default();
+        // prepend() builds from the tail, so the last element has to go in first
+        for data in values.into_iter().rev() {
+            list = list.prepend(data);
+        }
+        Ok(list)
+    }
+}
+
 #[test]
 fn third_list() {
     let binding = "Hello".to_string();
@@ -111,3 +145,19 @@ fn third_list() {
     assert_eq!(iter.next(), Some(&'l'));
     assert_eq!(iter.next(), Some(&'l'));
 }
+
+#[cfg(feature = "serde")]
+#[test]
+fn third_list_serde() {
+    let vec = "Hello".chars().collect::<Vec<_>>();
+    let list = PersistentList::<char>::from(vec.as_slice());
+
+    let json = serde_json::to_string(&list).unwrap();
+    assert_eq!(json, r#"["o","l","l","e","H"]"#);
+    let from_json: PersistentList<char> = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_json.iter().collect::<String>(), "olleH");
+
+    let bytes = bincode::serialize(&list.tail()).unwrap();
+    let from_bincode: PersistentList<char> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(from_bincode.iter().collect::<String>(), "lleH");
+}
@@ -0,0 +1,45 @@
+// Many tiny jobs, to compare how much the schedulers themselves cost:
+// cargo bench --manifest-path projects/web_server/Cargo.toml
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server::pool::{Scheduler, ThreadPool};
+
+const JOBS: u64 = 200_000;
+const ROUNDS: usize = 5;
+
+fn main() {
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    println!("{JOBS} jobs on {threads} threads, best of {ROUNDS} rounds");
+
+    for scheduler in [Scheduler::SharedQueue, Scheduler::WorkStealing] {
+        let pool = ThreadPool::with_scheduler(threads, scheduler);
+
+        let best = (0..ROUNDS)
+            .map(|_| {
+                let sum = AtomicU64::new(0);
+                let start = Instant::now();
+                pool.scope(|s| {
+                    for i in 0..JOBS {
+                        let sum = &sum;
+                        s.execute(move || {
+                            sum.fetch_add(black_box(i), Ordering::Relaxed);
+                        });
+                    }
+                });
+                let elapsed = start.elapsed();
+                assert_eq!(sum.load(Ordering::Relaxed), JOBS * (JOBS - 1) / 2);
+                elapsed
+            })
+            .min()
+            .unwrap_or(Duration::ZERO);
+
+        println!(
+            "{scheduler:?}: {best:?} ({:.0} jobs/s)",
+            JOBS as f64 / best.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{date::clf_date, request::Request};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `host ident user [time] "request line" status bytes`
+    Common,
+    /// Common plus the `Referer` and `User-Agent` headers.
+    #[default]
+    Combined,
+}
+
+/// Writes one line per response in the Common or Combined Log Format.
+///
+/// The time taken to serve the request is appended in microseconds, like Apache's
+/// `%D`. Missing fields are written as `-`.
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(sink: impl Write + Send + 'static, format: LogFormat) -> Self {
+        Self {
+            format,
+            sink: Mutex::new(Box::new(sink)),
+        }
+    }
+
+    pub fn stdout(format: LogFormat) -> Self {
+        Self::new(io::stdout(), format)
+    }
+
+    /// Logs a response of `bytes` body bytes. `request` is `None` when the request
+    /// couldn't be parsed.
+    pub fn log(
+        &self,
+        client: Option<SocketAddr>,
+        request: Option<&Request>,
+        status: u16,
+        bytes: u64,
+        elapsed: Duration,
+    ) {
+        let line = self.format_line(SystemTime::now(), client, request, status, bytes, elapsed);
+
+        // A failing sink shouldn't take the connection down with it
+        let mut sink = self.sink.lock().unwrap();
+        if let Err(e) = sink.write_all(line.as_bytes()).and_then(|()| sink.flush()) {
+            eprintln!("Couldn't write the access log: {e}");
+        }
+    }
+
+    fn format_line(
+        &self,
+        time: SystemTime,
+        client: Option<SocketAddr>,
+        request: Option<&Request>,
+        status: u16,
+        bytes: u64,
+        elapsed: Duration,
+    ) -> String {
+        let host = client.map_or("-".to_string(), |addr| addr.ip().to_string());
+        let request_line = request.map_or("-".to_string(), |request| {
+            let target = match &request.query {
+                Some(query) => format!("{}?{query}", request.path),
+                None => request.path.clone(),
+            };
+            escape(&format!("{} {target} {}", request.method, request.version))
+        });
+        let bytes = match bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+
+        let mut line = format!(
+            "{host} - - [{}] \"{request_line}\" {status} {bytes}",
+            clf_date(time)
+        );
+        if self.format == LogFormat::Combined {
+            let header = |name| {
+                request
+                    .and_then(|r| r.header(name))
+                    .map_or("-".to_string(), escape)
+            };
+            let _ = write!(
+                line,
+                " \"{}\" \"{}\"",
+                header("referer"),
+                header("user-agent")
+            );
+        }
+        let _ = writeln!(line, " {}", elapsed.as_micros());
+        line
+    }
+}
+
+/// Keeps client-controlled text from breaking out of its quotes or its line.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn log_formats() {
+    use crate::request::Limits;
+    use std::time::UNIX_EPOCH;
+
+    let raw = "GET /search?q=rust HTTP/1.1\r\nReferer: http://example.com/\r\nUser-Agent: curl \"8\"\r\n\r\n";
+    let request = Request::parse(&mut raw.as_bytes(), &Limits::default()).unwrap();
+    let time = UNIX_EPOCH + Duration::from_secs(784111777);
+    let client = Some("127.0.0.1:51234".parse().unwrap());
+    let elapsed = Duration::from_micros(1500);
+
+    let common = AccessLog::new(io::sink(), LogFormat::Common);
+    assert_eq!(
+        common.format_line(time, client, Some(&request), 200, 5, elapsed),
+        "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /search?q=rust HTTP/1.1\" 200 5 1500\n"
+    );
+
+    let combined = AccessLog::new(io::sink(), LogFormat::Combined);
+    assert_eq!(
+        combined.format_line(time, client, Some(&request), 200, 5, elapsed),
+        "127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /search?q=rust HTTP/1.1\" 200 5 \"http://example.com/\" \"curl \\\"8\\\"\" 1500\n"
+    );
+
+    // Unparseable request, empty body
+    assert_eq!(
+        combined.format_line(time, None, None, 400, 0, elapsed),
+        "- - - [06/Nov/1994:08:49:37 +0000] \"-\" 400 - \"-\" \"-\" 1500\n"
+    );
+}
@@ -1,49 +1,179 @@
-use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    thread,
-    time::Duration,
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{env, fs, iter, process, sync::Arc, thread, time::Duration};
+use web_server::{
+    access_log::{AccessLog, LogFormat},
+    metrics::RequestMetrics,
+    request::Request,
+    response::Response,
+    router::Router,
+    server::{Config, Server},
+    static_files::StaticFiles,
 };
-use web_server::ThreadPool;
 
 // Web server from Rust's book:
 // https://doc.rust-lang.org/book/
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:3737")?;
+    let settings = Settings::from_env()?;
+    let static_dir = settings.static_dir;
+    let metrics = Arc::new(RequestMetrics::new());
+
+    let server = Server::bind("127.0.0.1:3737", router(&static_dir, &metrics))?
+        .with_config(Config::default())
+        .with_threads(4)
+        .with_access_log(AccessLog::stdout(LogFormat::Combined))
+        .with_metrics(Arc::clone(&metrics));
     println!("Starting the WebServer at port 3737");
-    let pool = ThreadPool::new(4);
 
-    for stream in listener.incoming().take(3) {
-        let stream = stream?;
+    #[cfg(feature = "tls")]
+    let https = settings
+        .https
+        .map(|https| https_server(&https, &static_dir, &metrics))
+        .transpose()?;
+    #[cfg(not(feature = "tls"))]
+    let https: Option<Server> = None;
+
+    let handles = iter::once(&server)
+        .chain(&https)
+        .map(Server::shutdown_handle)
+        .collect::<Vec<_>>();
+    let https = https.map(|server| thread::spawn(move || server.run()));
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+    // The first signal starts a graceful shutdown, a second one exits right away
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    let signalled = handles.clone();
+    thread::spawn(move || {
+        let handles = signalled;
+        for signal in signals.forever() {
+            if handles[0].is_requested() {
+                eprintln!("Received signal {signal} again, exiting.");
+                process::exit(1);
+            }
+            println!("Received signal {signal}, shutting down.");
+            handles.iter().for_each(|handle| handle.shutdown());
+        }
+    });
+
+    // Should the HTTP server stop on an error, the HTTPS one is still stopped and
+    // waited for before reporting it
+    let result = server.run();
+    handles.iter().for_each(|handle| handle.shutdown());
+    if let Some(https) = https {
+        https.join().expect("HTTPS server panicked")?;
     }
+    result?;
 
     println!("Shutting down.");
     Ok(())
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
-    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+/// How the server is set up, from the environment:
+///
+/// - `WEB_SERVER_STATIC_DIR`, where `/static` is served from, `static/` in the crate
+///   by default.
+/// - `WEB_SERVER_TLS_CERT` and `WEB_SERVER_TLS_KEY`, the default certificate chain and
+///   its key as PEM files, to serve HTTPS too.
+/// - `WEB_SERVER_TLS_HOSTS`, optional, whitespace-separated `host=cert,key` entries
+///   for the certificates picked by SNI, e.g. `*.example.com=wild.pem,wild.key`.
+/// - `WEB_SERVER_TLS_PORT`, the HTTPS port, 3738 by default.
+struct Settings {
+    static_dir: String,
+    #[cfg(feature = "tls")]
+    https: Option<Https>,
+}
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", format!("{manifest_dir}/hello.html")),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
+#[cfg(feature = "tls")]
+struct Https {
+    port: u16,
+    tls: web_server::tls::TlsConfig,
+}
+
+impl Settings {
+    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            static_dir: env::var("WEB_SERVER_STATIC_DIR")
+                .unwrap_or_else(|_| format!("{}/static", env!("CARGO_MANIFEST_DIR"))),
+            #[cfg(feature = "tls")]
+            https: Https::from_env()?,
+        })
+    }
+}
 
-            ("HTTP/1.1 200 OK", format!("{manifest_dir}/hello.html"))
+#[cfg(feature = "tls")]
+impl Https {
+    /// `None` without a default certificate.
+    fn from_env() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let (Ok(cert), Ok(key)) = (
+            env::var("WEB_SERVER_TLS_CERT"),
+            env::var("WEB_SERVER_TLS_KEY"),
+        ) else {
+            return Ok(None);
+        };
+
+        let mut tls = web_server::tls::TlsConfig::new(cert, key);
+        for entry in env::var("WEB_SERVER_TLS_HOSTS")
+            .unwrap_or_default()
+            .split_whitespace()
+        {
+            let Some((host, (cert, key))) = entry
+                .split_once('=')
+                .and_then(|(host, files)| Some((host, files.split_once(',')?)))
+            else {
+                return Err(
+                    format!("WEB_SERVER_TLS_HOSTS: expected host=cert,key, got {entry}").into(),
+                );
+            };
+            tls = tls.with_host(host, cert, key);
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", format!("{manifest_dir}/404.html")),
-    };
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
+        let port = match env::var("WEB_SERVER_TLS_PORT") {
+            Ok(port) => port.parse::<u16>()?,
+            Err(_) => 3738,
+        };
+        Ok(Some(Self { port, tls }))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn https_server(
+    https: &Https,
+    static_dir: &str,
+    metrics: &Arc<RequestMetrics>,
+) -> Result<Server, Box<dyn std::error::Error>> {
+    let server = Server::bind(("127.0.0.1", https.port), router(static_dir, metrics))?
+        .with_tls(&https.tls)?
+        .with_threads(4)
+        .with_access_log(AccessLog::stdout(LogFormat::Combined))
+        .with_metrics(Arc::clone(metrics));
+    println!("Starting the HTTPS WebServer at port {}", https.port);
+    Ok(server)
+}
+
+fn router(static_dir: &str, metrics: &Arc<RequestMetrics>) -> Router {
+    let metrics = Arc::clone(metrics);
 
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+    Router::new()
+        .get("/", |_: &Request| page(200, "hello.html"))
+        .get("/sleep", |_: &Request| {
+            thread::sleep(Duration::from_secs(5));
+            page(200, "hello.html")
+        })
+        .get("/static/*path", StaticFiles::new(static_dir))
+        .get("/metrics", move |_: &Request| metrics.response())
+        .fallback(|_: &Request| page(404, "404.html"))
+}
+
+fn page(status: u16, filename: &str) -> Response {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
 
-    stream.write_all(response.as_bytes()).unwrap();
+    match fs::read_to_string(format!("{manifest_dir}/{filename}")) {
+        Ok(contents) => Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(contents),
+        Err(e) => {
+            eprintln!("Couldn't read {filename}: {e}");
+            Response::new(500)
+        }
+    }
 }
@@ -0,0 +1,150 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use crate::response::Response;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Label used for requests that didn't match any route.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Request counters and latency histograms by route, exposed in the Prometheus text
+/// format.
+///
+/// Routes are labeled by pattern (`/users/:id`) rather than path, so the number of
+/// series stays bounded.
+#[derive(Default)]
+pub struct RequestMetrics {
+    routes: Mutex<BTreeMap<String, RouteMetrics>>,
+}
+
+#[derive(Default)]
+struct RouteMetrics {
+    /// By method and status.
+    requests: BTreeMap<(String, u16), u64>,
+    /// Not cumulative, unlike the exposed `_bucket` series. The last one is `+Inf`.
+    buckets: [u64; BUCKETS.len() + 1],
+    count: u64,
+    sum: f64,
+}
+
+impl RequestMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let mut routes = self.routes.lock().unwrap();
+        let metrics = routes.entry(route.to_string()).or_default();
+
+        *metrics
+            .requests
+            .entry((method.to_string(), status))
+            .or_default() += 1;
+
+        let secs = elapsed.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|&le| secs <= le)
+            .unwrap_or(BUCKETS.len());
+        metrics.buckets[bucket] += 1;
+        metrics.count += 1;
+        metrics.sum += secs;
+    }
+
+    pub fn render(&self) -> String {
+        let routes = self.routes.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Requests served, by route, method and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (route, metrics) in routes.iter() {
+            for ((method, status), count) in &metrics.requests {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{route=\"{}\",method=\"{method}\",status=\"{status}\"}} {count}",
+                    escape_label(route)
+                );
+            }
+        }
+
+        out.push_str(
+            "# HELP http_request_duration_seconds Time taken to serve requests, by route.\n",
+        );
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (route, metrics) in routes.iter() {
+            let route = escape_label(route);
+            let mut cumulative = 0;
+            for (i, count) in metrics.buckets.iter().enumerate() {
+                cumulative += count;
+                let le = BUCKETS.get(i).map_or("+Inf".to_string(), f64::to_string);
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{route}\",le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{route=\"{route}\"}} {}",
+                metrics.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{route=\"{route}\"}} {}",
+                metrics.count
+            );
+        }
+
+        out
+    }
+
+    /// The rendered metrics, for a `/metrics` route.
+    pub fn response(&self) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .with_body(self.render())
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn prometheus_format() {
+    let metrics = RequestMetrics::new();
+    metrics.record("/users/:id", "GET", 200, Duration::from_micros(300));
+    metrics.record("/users/:id", "GET", 200, Duration::from_millis(20));
+    metrics.record("/users/:id", "DELETE", 405, Duration::from_millis(3));
+    metrics.record(UNMATCHED_ROUTE, "GET", 404, Duration::from_secs(5));
+
+    let rendered = metrics.render();
+    let lines = rendered.lines().collect::<Vec<_>>();
+    for expected in [
+        "# TYPE http_requests_total counter",
+        "http_requests_total{route=\"/users/:id\",method=\"DELETE\",status=\"405\"} 1",
+        "http_requests_total{route=\"/users/:id\",method=\"GET\",status=\"200\"} 2",
+        "http_requests_total{route=\"unmatched\",method=\"GET\",status=\"404\"} 1",
+        "# TYPE http_request_duration_seconds histogram",
+        "http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"0.0005\"} 1",
+        "http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"0.005\"} 2",
+        "http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"0.025\"} 3",
+        "http_request_duration_seconds_bucket{route=\"/users/:id\",le=\"+Inf\"} 3",
+        "http_request_duration_seconds_count{route=\"/users/:id\"} 3",
+        "http_request_duration_seconds_bucket{route=\"unmatched\",le=\"2.5\"} 0",
+        "http_request_duration_seconds_bucket{route=\"unmatched\",le=\"+Inf\"} 1",
+        "http_request_duration_seconds_sum{route=\"unmatched\"} 5",
+    ] {
+        assert!(
+            lines.contains(&expected),
+            "missing {expected} in\n{rendered}"
+        );
+    }
+
+    assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+}
@@ -0,0 +1,1098 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Queue capacity used by `ThreadPool::new`.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// How long workers above `min_threads` stay around without a job, by default.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Upper bound for a sleep of an idle work-stealing worker, in case a wakeup is missed.
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// How the workers get their jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheduler {
+    /// Every worker receives from the same channel, behind a mutex.
+    #[default]
+    SharedQueue,
+    /// Jobs go to a global injector. Each worker moves batches of them to its own deque
+    /// and steals from the other deques when it runs dry, so workers rarely contend
+    /// on the same lock.
+    WorkStealing,
+}
+
+/// State shared between the pool and its workers.
+struct Shared {
+    queue: Queue,
+    capacity: usize,
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    workers: Mutex<Workers>,
+    /// Workers alive, only changed with `workers` locked.
+    live: AtomicUsize,
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicUsize,
+    panicked: AtomicUsize,
+}
+
+/// Worker threads by id. There are `max_threads` ids, the free ones are reused when
+/// the pool grows.
+struct Workers {
+    /// Replaced when a worker is respawned after a panic.
+    threads: Vec<Option<thread::JoinHandle<()>>>,
+    /// Deques of the free ids in work-stealing mode. Their stealers stay registered,
+    /// so they're kept for the next worker with that id.
+    deques: Vec<Option<Deque<Job>>>,
+    /// Workers that retired after being idle, joined when the pool is dropped.
+    retired: Vec<thread::JoinHandle<()>>,
+}
+
+/// Why a worker stops asking for jobs.
+enum Exit {
+    /// The pool was dropped and the queue is drained.
+    Closed,
+    /// The worker was idle for `keep_alive` and the pool is above `min_threads`.
+    Idle,
+}
+
+enum Queue {
+    /// The sending half lives in `ThreadPool`, dropping it stops the workers.
+    Channel(Mutex<mpsc::Receiver<Job>>),
+    Stealing(Box<Stealing>),
+}
+
+struct Stealing {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    closed: AtomicBool,
+    /// Taken around sleeping and waking up, so a notification can't slip in between
+    /// the last look at the queue and the wait.
+    lock: Mutex<()>,
+    /// Workers waiting for a job to be pushed or the pool to be closed.
+    work: Waiters,
+    /// Producers waiting for a job to leave the full queue.
+    room: Waiters,
+}
+
+/// A condvar that is only notified when someone waits on it, and only once until the
+/// notified thread wakes up, so the hot paths rarely take the lock.
+struct Waiters {
+    condvar: Condvar,
+    waiting: AtomicUsize,
+    notified: AtomicBool,
+}
+
+impl Waiters {
+    fn new() -> Self {
+        Self {
+            condvar: Condvar::new(),
+            waiting: AtomicUsize::new(0),
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    /// Called with the lock held, before the last look at the condition.
+    fn register(&self) {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Called instead of `wait` when the last look found what it was waiting for.
+    fn unregister(&self) {
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn wait(&self, guard: MutexGuard<'_, ()>, timeout: Duration) {
+        let _ = self.condvar.wait_timeout(guard, timeout).unwrap();
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.notified.store(false, Ordering::SeqCst);
+    }
+
+    fn notify_one(&self, lock: &Mutex<()>) {
+        if self.waiting.load(Ordering::SeqCst) > 0 && !self.notified.swap(true, Ordering::SeqCst) {
+            let _guard = lock.lock().unwrap();
+            self.condvar.notify_one();
+        }
+    }
+
+    fn notify_all(&self, lock: &Mutex<()>) {
+        let _guard = lock.lock().unwrap();
+        self.condvar.notify_all();
+    }
+}
+
+impl Shared {
+    /// Blocks until there's a job to run.
+    fn next_job(&self, id: usize, local: &mut Option<Deque<Job>>) -> Result<Job, Exit> {
+        let job = match &self.queue {
+            Queue::Channel(receiver) => self.receive(receiver, id, local),
+            Queue::Stealing(stealing) => self.steal(stealing, id, local),
+        }?;
+
+        // Counted as active before it stops being queued, so `grow` never sees less
+        // work than there is
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        if let Queue::Stealing(stealing) = &self.queue {
+            stealing.room.notify_one(&stealing.lock);
+        }
+        Ok(job)
+    }
+
+    fn receive(
+        &self,
+        receiver: &Mutex<mpsc::Receiver<Job>>,
+        id: usize,
+        local: &mut Option<Deque<Job>>,
+    ) -> Result<Job, Exit> {
+        let mut deadline = self.idle_deadline();
+        loop {
+            // Idle workers queue up on the lock, by the time one gets it its deadline
+            // may be gone already
+            let receiver = receiver.lock().unwrap();
+            let Some(until) = deadline else {
+                return receiver.recv().map_err(|_| Exit::Closed);
+            };
+            match receiver.recv_timeout(until.saturating_duration_since(Instant::now())) {
+                Ok(job) => return Ok(job),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(Exit::Closed),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    drop(receiver);
+                    if self.retire(id, local) {
+                        return Err(Exit::Idle);
+                    }
+                    deadline = self.idle_deadline();
+                }
+            }
+        }
+    }
+
+    fn steal(
+        &self,
+        stealing: &Stealing,
+        id: usize,
+        local: &mut Option<Deque<Job>>,
+    ) -> Result<Job, Exit> {
+        let mut deadline = self.idle_deadline();
+        loop {
+            let deque = local.as_ref().expect("work-stealing workers own a deque");
+            if let Some(job) = stealing.find_job(deque) {
+                // Passes the wakeup on while there's work left for the sleepers
+                if !stealing.injector.is_empty() {
+                    stealing.work.notify_one(&stealing.lock);
+                }
+                return Ok(job);
+            }
+
+            let guard = stealing.lock.lock().unwrap();
+            stealing.work.register();
+            // A job counted as queued is either being pushed or in some deque, about to
+            // be stolen
+            if self.queued.load(Ordering::SeqCst) > 0 {
+                stealing.work.unregister();
+                drop(guard);
+                thread::yield_now();
+                continue;
+            }
+            if stealing.closed.load(Ordering::SeqCst) {
+                stealing.work.unregister();
+                return Err(Exit::Closed);
+            }
+
+            let mut timeout = IDLE_WAIT;
+            if let Some(until) = deadline {
+                let left = until.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    stealing.work.unregister();
+                    drop(guard);
+                    if self.retire(id, local) {
+                        return Err(Exit::Idle);
+                    }
+                    deadline = self.idle_deadline();
+                    continue;
+                }
+                timeout = timeout.min(left);
+            }
+            stealing.work.wait(guard, timeout);
+        }
+    }
+
+    /// When an idle worker may retire, `None` if the pool never shrinks.
+    fn idle_deadline(&self) -> Option<Instant> {
+        if self.min_threads == self.max_threads {
+            return None;
+        }
+        Instant::now().checked_add(self.keep_alive)
+    }
+
+    /// Lets an idle worker go, unless the pool is down to `min_threads`.
+    fn retire(&self, id: usize, local: &mut Option<Deque<Job>>) -> bool {
+        let mut workers = self.workers.lock().unwrap();
+        if self.live.load(Ordering::SeqCst) <= self.min_threads {
+            return false;
+        }
+
+        self.live.fetch_sub(1, Ordering::SeqCst);
+        // `grow` looks at `live` after queueing, so either it sees this worker gone or
+        // this worker sees the job and stays
+        if self.queued.load(Ordering::SeqCst) > 0 {
+            self.live.fetch_add(1, Ordering::SeqCst);
+            return false;
+        }
+
+        if let Some(deque) = workers.deques.get_mut(id) {
+            *deque = local.take();
+        }
+        if let Some(thread) = workers.threads[id].take() {
+            workers.retired.push(thread);
+        }
+        true
+    }
+
+    /// Spawns a worker if there are more jobs than workers to run them.
+    fn grow(self: &Arc<Self>) {
+        if !self.needs_worker() {
+            return;
+        }
+
+        let mut workers = self.workers.lock().unwrap();
+        if !self.needs_worker() {
+            return;
+        }
+        let Some(id) = workers.threads.iter().position(Option::is_none) else {
+            return;
+        };
+        let local = workers.deques.get_mut(id).and_then(Option::take);
+        workers.threads[id] = Some(Worker::spawn(id, Arc::clone(self), local));
+        self.live.fetch_add(1, Ordering::SeqCst);
+
+        // Retired workers are long gone by the time their id is reused
+        workers.retired.retain(|thread| !thread.is_finished());
+    }
+
+    fn needs_worker(&self) -> bool {
+        let live = self.live.load(Ordering::SeqCst);
+        let work = self.queued.load(Ordering::SeqCst) + self.active.load(Ordering::SeqCst);
+        live < self.max_threads && work > live
+    }
+
+    /// Takes a queue slot, if there's one left.
+    fn reserve(&self) -> bool {
+        self.queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                (queued < self.capacity).then_some(queued + 1)
+            })
+            .is_ok()
+    }
+}
+
+impl Stealing {
+    // From the crossbeam_deque docs: local deque first, then a batch from the
+    // injector, then the other workers
+    fn find_job(&self, local: &Deque<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn push(&self, job: Job) {
+        self.injector.push(job);
+        self.work.notify_one(&self.lock);
+    }
+}
+
+struct Worker;
+
+impl Worker {
+    fn spawn(id: usize, shared: Arc<Shared>, local: Option<Deque<Job>>) -> thread::JoinHandle<()> {
+        let mut builder = thread::Builder::new();
+        if let Some(name) = &shared.thread_name {
+            builder = builder.name(format!("{name}-{id}"));
+        }
+        if let Some(stack_size) = shared.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        builder
+            .spawn(move || {
+                // Respawns this worker if a job panics and unwinds the thread
+                let mut sentinel = Sentinel {
+                    id,
+                    shared: &shared,
+                    local,
+                };
+
+                loop {
+                    match shared.next_job(id, &mut sentinel.local) {
+                        Ok(job) => {
+                            job();
+
+                            shared.active.fetch_sub(1, Ordering::SeqCst);
+                            shared.completed.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Err(Exit::Idle) => {
+                            println!("Worker {id} idle; retiring.");
+                            break;
+                        }
+                        Err(Exit::Closed) => {
+                            println!("Worker {id} disconnected; shutting down.");
+                            break;
+                        }
+                    }
+                }
+
+                sentinel.cancel();
+            })
+            .expect("failed to spawn a worker thread")
+    }
+}
+
+struct Sentinel<'a> {
+    id: usize,
+    shared: &'a Arc<Shared>,
+    /// The worker's deque in work-stealing mode, handed over to the replacement so
+    /// the jobs already in it aren't lost.
+    local: Option<Deque<Job>>,
+}
+
+impl Sentinel<'_> {
+    fn cancel(mut self) {
+        drop(self.local.take());
+        mem::forget(self);
+    }
+}
+
+impl Drop for Sentinel<'_> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        println!("Worker {} panicked; respawning.", self.id);
+        self.shared.active.fetch_sub(1, Ordering::SeqCst);
+        self.shared.panicked.fetch_add(1, Ordering::SeqCst);
+
+        // The new handle is in place before this thread finishes, so `Drop for ThreadPool`
+        // finds it after joining the panicked one
+        let thread = Worker::spawn(self.id, Arc::clone(self.shared), self.local.take());
+        self.shared.workers.lock().unwrap().threads[self.id] = Some(thread);
+    }
+}
+
+/// Snapshot of the pool counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Metrics {
+    /// Worker threads alive.
+    pub threads: usize,
+    /// Jobs waiting in the queue.
+    pub queued: usize,
+    /// Jobs being run right now.
+    pub active: usize,
+    pub completed: usize,
+    pub panicked: usize,
+}
+
+/// Returned by `try_execute` when the queue is at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread pool queue is full")
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+/// Returned by `JobHandle::join` when the job panicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobPanicked;
+
+impl fmt::Display for JobPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "job panicked")
+    }
+}
+
+impl std::error::Error for JobPanicked {}
+
+/// Gives access to the value returned by a job sent with `submit`.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> JobHandle<T> {
+    /// Blocks until the job finishes.
+    pub fn join(self) -> Result<T, JobPanicked> {
+        // The sender is dropped without sending only if the job unwinds
+        self.receiver.recv().map_err(|_| JobPanicked)
+    }
+}
+
+/// Configures a `ThreadPool`.
+///
+/// The pool starts with `min_threads` workers and spawns more, up to `max_threads`,
+/// while there are jobs waiting and no idle worker to run them. Workers above the
+/// minimum retire after going `keep_alive` without a job.
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    queue_capacity: usize,
+    scheduler: Scheduler,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        Self {
+            min_threads: 1,
+            max_threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            scheduler: Scheduler::default(),
+            thread_name: None,
+            stack_size: None,
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// A fixed number of workers, same as setting both `min_threads` and `max_threads`.
+    pub fn with_threads(self, threads: usize) -> Self {
+        self.with_min_threads(threads).with_max_threads(threads)
+    }
+
+    /// Workers started upfront and kept even when idle, 1 by default.
+    pub fn with_min_threads(mut self, threads: usize) -> Self {
+        self.min_threads = threads;
+        self
+    }
+
+    /// Upper bound for the workers, the available parallelism by default.
+    pub fn with_max_threads(mut self, threads: usize) -> Self {
+        self.max_threads = threads;
+        self
+    }
+
+    /// How long a worker above `min_threads` waits for a job before retiring.
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Jobs waiting for a worker before `execute` blocks. Zero makes every job a
+    /// hand-off to an idle worker, which only the shared queue supports.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Workers are named `{name}-{id}`, unnamed by default.
+    pub fn with_thread_name(mut self, name: &str) -> Self {
+        self.thread_name = Some(name.to_string());
+        self
+    }
+
+    /// Stack size of the workers in bytes, the `std::thread` default otherwise.
+    pub fn with_stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is zero or below `min_threads`, or if the queue capacity
+    /// is zero with the work-stealing scheduler.
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max_threads > 0);
+        assert!(self.min_threads <= self.max_threads);
+        // Jobs only reach the workers through the injector, there's no hand-off
+        assert!(
+            self.queue_capacity > 0 || self.scheduler != Scheduler::WorkStealing,
+            "work stealing needs a queue capacity of at least 1"
+        );
+
+        let (queue, sender, deques) = match self.scheduler {
+            Scheduler::SharedQueue => {
+                let (sender, receiver) = mpsc::sync_channel(self.queue_capacity);
+                let queue = Queue::Channel(Mutex::new(receiver));
+                (queue, Some(sender), Vec::new())
+            }
+            Scheduler::WorkStealing => {
+                let deques = (0..self.max_threads)
+                    .map(|_| Deque::new_fifo())
+                    .collect::<Vec<_>>();
+                let queue = Queue::Stealing(Box::new(Stealing {
+                    injector: Injector::new(),
+                    stealers: deques.iter().map(Deque::stealer).collect(),
+                    closed: AtomicBool::new(false),
+                    lock: Mutex::new(()),
+                    work: Waiters::new(),
+                    room: Waiters::new(),
+                }));
+                (queue, None, deques.into_iter().map(Some).collect())
+            }
+        };
+
+        let shared = Arc::new(Shared {
+            queue,
+            capacity: self.queue_capacity,
+            min_threads: self.min_threads,
+            max_threads: self.max_threads,
+            keep_alive: self.keep_alive,
+            thread_name: self.thread_name,
+            stack_size: self.stack_size,
+            workers: Mutex::new(Workers {
+                threads: (0..self.max_threads).map(|_| None).collect(),
+                deques,
+                retired: Vec::new(),
+            }),
+            live: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+        });
+
+        {
+            let mut workers = shared.workers.lock().unwrap();
+            for id in 0..self.min_threads {
+                let local = workers.deques.get_mut(id).and_then(Option::take);
+                workers.threads[id] = Some(Worker::spawn(id, Arc::clone(&shared), local));
+                shared.live.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        ThreadPool { shared, sender }
+    }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    /// Only used by `Scheduler::SharedQueue`.
+    sender: Option<mpsc::SyncSender<Job>>,
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool.
+    ///
+    /// The size is the number of threads in the pool, the queue holds up to
+    /// `DEFAULT_QUEUE_CAPACITY` jobs.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::builder().with_threads(size).build()
+    }
+
+    /// Create a new ThreadPool whose queue holds up to `capacity` jobs waiting for a
+    /// worker. Once it's full, `execute` blocks and `try_execute` fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
+    pub fn with_capacity(size: usize, capacity: usize) -> ThreadPool {
+        ThreadPool::builder()
+            .with_threads(size)
+            .with_queue_capacity(capacity)
+            .build()
+    }
+
+    /// Create a new ThreadPool that hands out jobs with the given scheduler.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
+    pub fn with_scheduler(size: usize, scheduler: Scheduler) -> ThreadPool {
+        ThreadPool::builder()
+            .with_threads(size)
+            .with_scheduler(scheduler)
+            .build()
+    }
+
+    /// Configure a pool that grows and shrinks with the load, among other settings.
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::default()
+    }
+
+    /// Queues the job, waiting for room if the queue is full.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.send(Box::new(f));
+    }
+
+    fn send(&self, job: Job) {
+        self.enqueue(job);
+        self.shared.grow();
+    }
+
+    fn enqueue(&self, job: Job) {
+        match &self.shared.queue {
+            Queue::Channel(_) => {
+                self.shared.queued.fetch_add(1, Ordering::SeqCst);
+                // The send waits for a worker once the queue is full, or always with no
+                // room at all, so one must be running for it
+                self.shared.grow();
+                self.sender.as_ref().unwrap().send(job).unwrap();
+            }
+            Queue::Stealing(stealing) => {
+                while !self.shared.reserve() {
+                    let guard = stealing.lock.lock().unwrap();
+                    stealing.room.register();
+                    if self.shared.reserve() {
+                        stealing.room.unregister();
+                        break;
+                    }
+                    stealing.room.wait(guard, IDLE_WAIT);
+                }
+                stealing.push(job);
+            }
+        }
+    }
+
+    /// Queues the job only if there's room for it.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFull>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+
+        match &self.shared.queue {
+            Queue::Channel(_) => {
+                // Counted before sending, a worker could pick the job up right away
+                self.shared.queued.fetch_add(1, Ordering::SeqCst);
+                match self.sender.as_ref().unwrap().try_send(job) {
+                    Ok(()) => {
+                        self.shared.grow();
+                        Ok(())
+                    }
+                    Err(mpsc::TrySendError::Full(_)) => {
+                        self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        Err(QueueFull)
+                    }
+                    Err(mpsc::TrySendError::Disconnected(_)) => {
+                        unreachable!("workers outlive the pool")
+                    }
+                }
+            }
+            Queue::Stealing(stealing) => {
+                if !self.shared.reserve() {
+                    return Err(QueueFull);
+                }
+                stealing.push(job);
+                self.shared.grow();
+                Ok(())
+            }
+        }
+    }
+
+    /// Like `execute`, returning a handle to wait for the job's result.
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.execute(move || {
+            let _ = sender.send(f());
+        });
+        JobHandle { receiver }
+    }
+
+    /// Runs `f` with a `Scope` whose jobs can borrow from the caller's stack, and waits
+    /// for all of them to finish before returning.
+    ///
+    /// Calling it from one of the pool's own jobs can deadlock if every worker ends up
+    /// waiting on a scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics after all the jobs finished if any of them panicked.
+    pub fn scope<'scope, F, R>(&'scope self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'scope>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                panicked: AtomicBool::new(false),
+            }),
+            _marker: PhantomData,
+        };
+
+        // Waits even if `f` panics, its jobs may still be borrowing
+        let wait = ScopeWait(&scope.state);
+        let result = f(&scope);
+        drop(wait);
+
+        if scope.state.panicked.load(Ordering::SeqCst) {
+            panic!("a scoped job panicked");
+        }
+        result
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            threads: self.shared.live.load(Ordering::SeqCst),
+            queued: self.shared.queued.load(Ordering::SeqCst),
+            active: self.shared.active.load(Ordering::SeqCst),
+            completed: self.shared.completed.load(Ordering::SeqCst),
+            panicked: self.shared.panicked.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Queue::Stealing(stealing) = &self.shared.queue {
+            stealing.closed.store(true, Ordering::SeqCst);
+            stealing.work.notify_all(&stealing.lock);
+        }
+
+        let size = self.shared.workers.lock().unwrap().threads.len();
+        for id in 0..size {
+            // A worker that panics puts its replacement in the slot before exiting
+            loop {
+                let thread = self.shared.workers.lock().unwrap().threads[id].take();
+                let Some(thread) = thread else { break };
+                println!("Shutting down worker {id}");
+                let _ = thread.join();
+            }
+        }
+
+        let retired = mem::take(&mut self.shared.workers.lock().unwrap().retired);
+        for thread in retired {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Lets jobs borrow data that outlives the `ThreadPool::scope` call.
+pub struct Scope<'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    /// Makes `'scope` invariant, like `std::thread::Scope`.
+    _marker: PhantomData<&'scope mut &'scope ()>,
+}
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    panicked: AtomicBool,
+}
+
+impl<'scope> Scope<'scope> {
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+
+        let state = Arc::clone(&self.state);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let _done = ScopeJobDone(state);
+            f();
+        });
+        // SAFETY: `ThreadPool::scope` doesn't return until every job of the scope has
+        // run (`ScopeJobDone` is dropped even if the job panics), so nothing borrowed
+        // for `'scope` is used after it ends.
+        let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.send(job);
+    }
+}
+
+struct ScopeJobDone(Arc<ScopeState>);
+
+impl Drop for ScopeJobDone {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.panicked.store(true, Ordering::SeqCst);
+        }
+        let mut pending = self.0.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+struct ScopeWait<'a>(&'a ScopeState);
+
+impl Drop for ScopeWait<'_> {
+    fn drop(&mut self) {
+        let pending = self.0.pending.lock().unwrap();
+        drop(
+            self.0
+                .done
+                .wait_while(pending, |pending| *pending > 0)
+                .unwrap(),
+        );
+    }
+}
+
+#[test]
+fn bounded_queue_and_results() {
+    let pool = ThreadPool::with_capacity(1, 1);
+    let (release, blocked) = mpsc::channel::<()>();
+
+    // The only worker is busy and the single queue slot gets taken
+    pool.execute(move || blocked.recv().unwrap());
+    while pool.metrics().active == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let queued = pool.submit(|| 21 * 2);
+    assert_eq!(pool.try_execute(|| {}), Err(QueueFull));
+    assert_eq!(pool.metrics().queued, 1);
+
+    release.send(()).unwrap();
+    assert_eq!(queued.join(), Ok(42));
+    assert!(pool.try_execute(|| {}).is_ok());
+}
+
+#[test]
+fn panicking_jobs_respawn_workers() {
+    for scheduler in [Scheduler::SharedQueue, Scheduler::WorkStealing] {
+        let pool = ThreadPool::with_scheduler(2, scheduler);
+
+        for _ in 0..4 {
+            assert_eq!(
+                pool.submit(|| panic!("boom")).join(),
+                Err::<(), _>(JobPanicked)
+            );
+        }
+
+        // Both workers are still around to run jobs in parallel
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let handles = (0..2)
+            .map(|i| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                    i
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results, [0, 1]);
+
+        // The counters are updated right after the result is handed over
+        let expected = Metrics {
+            threads: 2,
+            queued: 0,
+            active: 0,
+            completed: 2,
+            panicked: 4,
+        };
+        let start = std::time::Instant::now();
+        while pool.metrics() != expected {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::yield_now();
+        }
+    }
+}
+
+#[test]
+fn work_stealing_scope() {
+    let pool = ThreadPool::with_scheduler(4, Scheduler::WorkStealing);
+
+    // Jobs borrow the chunks straight from the stack
+    let mut numbers = (0..10_000_u64).collect::<Vec<_>>();
+    let total = AtomicUsize::new(0);
+    pool.scope(|s| {
+        for chunk in numbers.chunks_mut(10) {
+            let total = &total;
+            s.execute(move || {
+                for n in chunk.iter_mut() {
+                    *n *= 2;
+                }
+                total.fetch_add(chunk.len(), Ordering::SeqCst);
+            });
+        }
+    });
+    assert_eq!(total.load(Ordering::SeqCst), 10_000);
+    assert_eq!(numbers.iter().sum::<u64>(), 9_999 * 10_000);
+
+    let results = (0..100)
+        .map(|i| pool.submit(move || i * i))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|h| h.join().unwrap())
+        .sum::<u64>();
+    assert_eq!(results, (0..100).map(|i| i * i).sum());
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pool.scope(|s| {
+            s.execute(|| panic!("boom"));
+            s.execute(|| {});
+        })
+    }));
+    assert!(panicked.is_err());
+
+    let small = ThreadPool::builder()
+        .with_threads(1)
+        .with_queue_capacity(1)
+        .with_scheduler(Scheduler::WorkStealing)
+        .build();
+    let (release, blocked) = mpsc::channel::<()>();
+    small.execute(move || blocked.recv().unwrap());
+    while small.metrics().active == 0 {
+        thread::yield_now();
+    }
+    small.execute(|| {});
+    assert_eq!(small.try_execute(|| {}), Err(QueueFull));
+    release.send(()).unwrap();
+
+    let unbuffered = std::panic::catch_unwind(|| {
+        ThreadPool::builder()
+            .with_queue_capacity(0)
+            .with_scheduler(Scheduler::WorkStealing)
+            .build()
+    });
+    assert!(unbuffered.is_err());
+}
+
+#[test]
+fn unbuffered_queue_spawns_workers() {
+    // No worker to start with and none idle for the second job, each send has to
+    // spawn the worker that receives it
+    let pool = ThreadPool::builder()
+        .with_min_threads(0)
+        .with_max_threads(2)
+        .with_queue_capacity(0)
+        .build();
+    let (started, running) = mpsc::channel();
+    let (release, blocked) = mpsc::channel::<()>();
+    let blocked = Arc::new(Mutex::new(blocked));
+    let sending = thread::spawn(move || {
+        for _ in 0..2 {
+            let (started, blocked) = (started.clone(), Arc::clone(&blocked));
+            pool.execute(move || {
+                started.send(()).unwrap();
+                let _ = blocked.lock().unwrap().recv();
+            });
+        }
+        pool
+    });
+
+    for _ in 0..2 {
+        running.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    drop(release);
+    assert_eq!(sending.join().unwrap().metrics().threads, 2);
+}
+
+#[test]
+fn dynamic_sizing() {
+    use std::sync::Barrier;
+
+    for scheduler in [Scheduler::SharedQueue, Scheduler::WorkStealing] {
+        let pool = ThreadPool::builder()
+            .with_min_threads(1)
+            .with_max_threads(3)
+            .with_keep_alive(Duration::from_millis(50))
+            .with_scheduler(scheduler)
+            .with_thread_name("sized")
+            .with_stack_size(32 << 20)
+            .build();
+        assert_eq!(pool.metrics().threads, 1);
+
+        let wait_for = |threads: usize| {
+            let start = Instant::now();
+            while pool.metrics().threads != threads {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        // Three jobs only meet at the barrier if the pool grew to run them together
+        let started = Arc::new(Barrier::new(4));
+        let finish = Arc::new(Barrier::new(4));
+        let handles = (0..3)
+            .map(|_| {
+                let (started, finish) = (Arc::clone(&started), Arc::clone(&finish));
+                pool.submit(move || {
+                    started.wait();
+                    finish.wait();
+                    thread::current().name().map(str::to_string)
+                })
+            })
+            .collect::<Vec<_>>();
+        started.wait();
+        assert_eq!(pool.metrics().threads, 3);
+
+        // At the maximum, the job waits in the queue
+        let queued = pool.submit(|| 1);
+        assert_eq!(pool.metrics().threads, 3);
+        assert_eq!(pool.metrics().queued, 1);
+
+        finish.wait();
+        for handle in handles {
+            let name = handle.join().unwrap().unwrap();
+            assert!(name.starts_with("sized-"), "{name}");
+        }
+        assert_eq!(queued.join(), Ok(1));
+
+        // Idle workers retire down to the minimum, and the pool grows again
+        wait_for(1);
+        let barrier = Arc::new(Barrier::new(3));
+        let handles = (0..2)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+        barrier.wait();
+        assert_eq!(pool.metrics().threads, 2);
+        handles.into_iter().for_each(|h| h.join().unwrap());
+        wait_for(1);
+
+        // Fits in the configured stack, not in the 2MiB default
+        let big = pool.submit(|| {
+            let buffer = std::hint::black_box([1_u8; 3 << 20]);
+            buffer.iter().map(|&b| b as usize).sum::<usize>()
+        });
+        assert_eq!(big.join(), Ok(3 << 20));
+    }
+}
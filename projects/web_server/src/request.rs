@@ -0,0 +1,512 @@
+use std::{collections::HashMap, fmt, io, io::prelude::*, str::FromStr};
+
+/// Upper bounds applied while reading a request off the wire.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Bytes allowed for the request line plus all the header lines.
+    pub max_header_bytes: usize,
+    pub max_headers: usize,
+    /// Bytes allowed for the body, after removing the chunked framing.
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 8 * 1024,
+            max_headers: 100,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Methods are case-sensitive
+        match s {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "PATCH" => Ok(Method::Patch),
+            "OPTIONS" => Ok(Method::Options),
+            _ if !s.is_empty() && s.bytes().all(is_token_byte) => {
+                Err(ParseError::UnsupportedMethod)
+            }
+            _ => Err(ParseError::Malformed("invalid method")),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let method = match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        };
+        f.write_str(method)
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The peer closed the connection before sending anything.
+    Closed,
+    Io(io::Error),
+    Malformed(&'static str),
+    UnsupportedMethod,
+    UnsupportedVersion,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl ParseError {
+    /// The status code that should be sent back for this error.
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::UnsupportedMethod => 501,
+            ParseError::UnsupportedVersion => 505,
+            ParseError::HeadersTooLarge => 431,
+            ParseError::BodyTooLarge => 413,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Closed => write!(f, "connection closed"),
+            ParseError::Io(e) => write!(f, "io error: {e}"),
+            ParseError::Malformed(reason) => write!(f, "malformed request: {reason}"),
+            ParseError::UnsupportedMethod => write!(f, "unsupported method"),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(value: io::Error) -> Self {
+        ParseError::Io(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: String,
+    /// Header names are stored lowercased, repeated headers are joined with ", ".
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// Filled by the `Router` from the matched pattern, e.g. `id` for `/users/:id`.
+    pub params: HashMap<String, String>,
+    /// Pattern of the route whose path matched, set by the `Router`.
+    pub route: Option<String>,
+}
+
+impl Request {
+    /// Reads exactly one request from the reader, leaving anything after it
+    /// (e.g. a pipelined request) in the reader.
+    pub fn parse<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, ParseError> {
+        let mut budget = limits.max_header_bytes;
+
+        // Some clients send empty lines between requests, those are ignored
+        let request_line = loop {
+            match read_line(reader, &mut budget)? {
+                None => return Err(ParseError::Closed),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::Malformed("invalid request line"));
+        };
+
+        let method = method.parse::<Method>()?;
+        if !target.starts_with('/') {
+            return Err(ParseError::Malformed("invalid request target"));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+        match version {
+            "HTTP/1.1" | "HTTP/1.0" => {}
+            v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+            _ => return Err(ParseError::Malformed("invalid version")),
+        }
+
+        let headers = read_headers(reader, &mut budget, limits.max_headers)?;
+
+        let mut request = Request {
+            method,
+            path,
+            query,
+            version: version.to_string(),
+            headers,
+            body: Vec::new(),
+            params: HashMap::new(),
+            route: None,
+        };
+        request.body = request.read_body(reader, limits)?;
+
+        Ok(request)
+    }
+
+    /// Case-insensitive header lookup.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    fn read_body<R: BufRead>(
+        &self,
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Vec<u8>, ParseError> {
+        let chunked = match self.header("transfer-encoding") {
+            None => false,
+            Some(te) if te.eq_ignore_ascii_case("chunked") => true,
+            Some(_) => return Err(ParseError::Malformed("unsupported transfer-encoding")),
+        };
+        let content_length = self.header("content-length");
+
+        match (chunked, content_length) {
+            // Both at once is a classic request smuggling vector
+            (true, Some(_)) => Err(ParseError::Malformed(
+                "both content-length and transfer-encoding",
+            )),
+            (true, None) => read_chunked_body(reader, limits),
+            (false, Some(length)) => {
+                // parse() would take a leading '+', which other parsers on the path may not
+                let length = Some(length)
+                    .filter(|length| {
+                        !length.is_empty() && length.bytes().all(|b| b.is_ascii_digit())
+                    })
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .ok_or(ParseError::Malformed("invalid content-length"))?;
+                if length > limits.max_body_bytes {
+                    return Err(ParseError::BodyTooLarge);
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).map_err(eof_is_malformed)?;
+                Ok(body)
+            }
+            (false, None) => Ok(Vec::new()),
+        }
+    }
+}
+
+fn read_headers<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    max_headers: usize,
+) -> Result<HashMap<String, String>, ParseError> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+
+    loop {
+        let line = read_line(reader, budget)?.ok_or(ParseError::Malformed("unexpected eof"))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() >= max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ParseError::Malformed("invalid header"))?;
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(ParseError::Malformed("invalid header name"));
+        }
+
+        let name = name.to_ascii_lowercase();
+        let value = value.trim();
+        if name == "content-length" && headers.contains_key(&name) {
+            return Err(ParseError::Malformed("repeated content-length"));
+        }
+        headers
+            .entry(name)
+            .and_modify(|v| {
+                v.push_str(", ");
+                v.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+}
+
+fn read_chunked_body<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    // Chunk size lines and trailers share the header budget
+    let mut budget = limits.max_header_bytes;
+
+    loop {
+        let line =
+            read_line(reader, &mut budget)?.ok_or(ParseError::Malformed("unexpected eof"))?;
+        // Chunk extensions are allowed after a ';', we don't use them
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = Some(size)
+            .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or(ParseError::Malformed("invalid chunk size"))?;
+
+        if size == 0 {
+            break;
+        }
+        // The body never exceeds the limit, so this can't underflow, unlike adding
+        // a size from the client could overflow
+        if size > limits.max_body_bytes - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(eof_is_malformed)?;
+
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf).map_err(eof_is_malformed)?;
+        if &crlf != b"\r\n" {
+            return Err(ParseError::Malformed("missing chunk terminator"));
+        }
+    }
+
+    // Trailers are read to keep the stream in sync, but dropped
+    loop {
+        match read_line(reader, &mut budget)? {
+            Some(line) if line.is_empty() => return Ok(body),
+            Some(_) => continue,
+            None => return Err(ParseError::Malformed("unexpected eof")),
+        }
+    }
+}
+
+/// Reads a single line without its line ending, charging its length to the budget.
+/// Returns `None` on EOF before any byte was read.
+fn read_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    // +1 so a line that exactly fits the budget can still be told apart from one that overflows
+    let read = reader
+        .by_ref()
+        .take(*budget as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    // Even with its newline in the extra byte, the line didn't fit
+    if read > *budget {
+        return Err(ParseError::HeadersTooLarge);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(ParseError::Malformed("unexpected eof"));
+    }
+    *budget -= read;
+
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::Malformed("invalid utf-8"))
+}
+
+fn eof_is_malformed(e: io::Error) -> ParseError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => ParseError::Malformed("unexpected eof"),
+        _ => ParseError::Io(e),
+    }
+}
+
+// RFC 9110 token characters, used for methods and header names
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[test]
+fn parse_request() {
+    let raw = "POST /users?active=true HTTP/1.1\r\n\
+               Host: localhost\r\n\
+               Accept: text/html\r\n\
+               accept: application/json\r\n\
+               Content-Length: 5\r\n\
+               \r\n\
+               helloGET / HTTP/1.1\r\n\r\n";
+    let mut reader = raw.as_bytes();
+
+    let request = Request::parse(&mut reader, &Limits::default()).unwrap();
+    assert_eq!(request.method, Method::Post);
+    assert_eq!(request.path, "/users");
+    assert_eq!(request.query.as_deref(), Some("active=true"));
+    assert_eq!(request.version, "HTTP/1.1");
+    assert_eq!(request.header("HOST"), Some("localhost"));
+    assert_eq!(
+        request.header("accept"),
+        Some("text/html, application/json")
+    );
+    assert_eq!(request.body, b"hello");
+
+    // The pipelined request is left untouched
+    let request = Request::parse(&mut reader, &Limits::default()).unwrap();
+    assert_eq!(request.method, Method::Get);
+    assert_eq!(request.path, "/");
+    assert!(request.body.is_empty());
+
+    assert!(matches!(
+        Request::parse(&mut reader, &Limits::default()),
+        Err(ParseError::Closed)
+    ));
+}
+
+#[test]
+fn parse_chunked_and_invalid_requests() {
+    let raw = "PUT /upload HTTP/1.1\r\n\
+               Transfer-Encoding: chunked\r\n\
+               \r\n\
+               5;ext=1\r\nhello\r\n\
+               7\r\n, world\r\n\
+               0\r\n\
+               Trailer: ignored\r\n\
+               \r\n";
+    let request = Request::parse(&mut raw.as_bytes(), &Limits::default()).unwrap();
+    assert_eq!(request.body, b"hello, world");
+
+    let parse = |raw: &str, limits: &Limits| Request::parse(&mut raw.as_bytes(), limits);
+    let limits = Limits::default();
+
+    assert!(matches!(
+        parse("GET /\r\n\r\n", &limits),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse("get / HTTP/1.1\r\n\r\n", &limits),
+        Err(ParseError::UnsupportedMethod)
+    ));
+    assert!(matches!(
+        parse("GET / HTTP/2.0\r\n\r\n", &limits),
+        Err(ParseError::UnsupportedVersion)
+    ));
+    assert!(matches!(
+        parse("GET / HTTP/1.1\r\nno-colon\r\n\r\n", &limits),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse("POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\n", &limits),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse(
+            "POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            &limits
+        ),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+a\r\n0123456789\r\n0\r\n\r\n",
+            &limits
+        ),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse(
+            "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort",
+            &limits
+        ),
+        Err(ParseError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse(
+            "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+            &limits
+        ),
+        Err(ParseError::Malformed(_))
+    ));
+
+    let small = Limits {
+        max_header_bytes: 48,
+        max_headers: 1,
+        max_body_bytes: 4,
+    };
+    assert!(matches!(
+        parse(
+            "GET / HTTP/1.1\r\nX-Long: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n\r\n",
+            &small
+        ),
+        Err(ParseError::HeadersTooLarge)
+    ));
+    assert!(matches!(
+        parse("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n", &small),
+        Err(ParseError::HeadersTooLarge)
+    ));
+    assert!(matches!(
+        parse("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello", &small),
+        Err(ParseError::BodyTooLarge)
+    ));
+    assert!(matches!(
+        parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             1\r\na\r\nffffffffffffffff\r\n",
+            &limits
+        ),
+        Err(ParseError::BodyTooLarge)
+    ));
+}
+
+#[test]
+fn header_budget() {
+    let parse = |raw: &str, max_header_bytes: usize| {
+        let limits = Limits {
+            max_header_bytes,
+            ..Limits::default()
+        };
+        Request::parse(&mut raw.as_bytes(), &limits)
+    };
+
+    // 20 bytes of request line and 2 of blank line
+    let raw = "GET /abcd HTTP/1.1\r\n\r\n";
+    assert!(parse(raw, 22).is_ok());
+    assert!(matches!(parse(raw, 21), Err(ParseError::HeadersTooLarge)));
+    assert!(matches!(parse(raw, 19), Err(ParseError::HeadersTooLarge)));
+
+    // Skipped blank lines count too, there's no streaming them forever
+    let raw = format!("{}GET / HTTP/1.1\r\n\r\n", "\n".repeat(100));
+    assert!(matches!(parse(&raw, 64), Err(ParseError::HeadersTooLarge)));
+}
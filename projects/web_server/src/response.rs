@@ -0,0 +1,484 @@
+use std::{
+    fmt,
+    io::{self, prelude::*},
+};
+
+use flate2::{
+    read::{GzEncoder as GzReader, ZlibEncoder as ZlibReader},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+use crate::request::Request;
+
+/// Bodies smaller than this aren't worth compressing, the headers alone would eat
+/// most of the savings.
+const MIN_COMPRESSED_LEN: u64 = 256;
+
+/// Size of the chunks a streamed body is sent in.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Copied to the socket as it's read, so big files never sit in memory.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        length: u64,
+    },
+    /// Of unknown length, sent with `Transfer-Encoding: chunked`.
+    Stream(Box<dyn Read + Send>),
+    /// Left out of the answer to a HEAD request, only its framing is sent: the
+    /// length it would have had, or chunked if that isn't known.
+    Omitted(Option<u64>),
+}
+
+impl Body {
+    /// `None` for a `Stream`, whose length isn't known until it's sent.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader { length, .. } => Some(*length),
+            Body::Stream(_) => None,
+            Body::Omitted(length) => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body contents, `None` if it's streamed from a reader or omitted.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Reader { .. } | Body::Stream(_) | Body::Omitted(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Reader { length, .. } => {
+                f.debug_struct("Reader").field("length", length).finish()
+            }
+            Body::Stream(_) => f.write_str("Stream"),
+            Body::Omitted(length) => f.debug_tuple("Omitted").field(length).finish(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    pub fn with_reader(mut self, reader: impl Read + Send + 'static, length: u64) -> Self {
+        self.body = Body::Reader {
+            reader: Box::new(reader),
+            length,
+        };
+        self
+    }
+
+    /// Streams the body as it's read, in chunks, when its length isn't known upfront.
+    pub fn with_stream(mut self, reader: impl Read + Send + 'static) -> Self {
+        self.body = Body::Stream(Box::new(reader));
+        self
+    }
+
+    /// Compresses the body with gzip or deflate, whichever the request prefers, if it
+    /// is text and long enough to be worth it.
+    ///
+    /// Known-length bodies streamed from a reader are compressed on the fly and sent
+    /// chunked. A strong `ETag` becomes weak, the bytes sent no longer match it.
+    pub fn compress_for(mut self, request: &Request) -> Self {
+        let compressible = self.header("content-type").is_some_and(is_compressible)
+            && self.header("content-encoding").is_none()
+            && !matches!(self.status, 100..=199 | 204 | 304)
+            && !matches!(self.body, Body::Omitted(_));
+        if !compressible {
+            return self;
+        }
+
+        // Caches must not hand a compressed response to clients that can't decode it
+        self.headers
+            .push(("Vary".to_string(), "Accept-Encoding".to_string()));
+        let Some(encoding) = request.header("accept-encoding").and_then(negotiate) else {
+            return self;
+        };
+        if self.body.len().is_some_and(|len| len < MIN_COMPRESSED_LEN) {
+            return self;
+        }
+
+        self.body = match self.body {
+            Body::Bytes(bytes) => match encoding.compress(&bytes) {
+                Ok(compressed) => Body::Bytes(compressed),
+                Err(_) => {
+                    return Response {
+                        body: Body::Bytes(bytes),
+                        ..self
+                    }
+                }
+            },
+            Body::Reader { reader, length } => encoding.compress_stream(reader.take(length)),
+            Body::Stream(reader) => encoding.compress_stream(reader),
+            omitted @ Body::Omitted(_) => omitted,
+        };
+        self.headers
+            .push(("Content-Encoding".to_string(), encoding.to_string()));
+        for (name, value) in &mut self.headers {
+            if name.eq_ignore_ascii_case("etag") && !value.starts_with("W/") {
+                *value = format!("W/{value}");
+            }
+        }
+        self
+    }
+
+    /// Reads a streamed body into memory, for HTTP/1.0 clients that can't receive it
+    /// chunked.
+    pub fn buffered(mut self) -> io::Result<Self> {
+        if let Body::Stream(reader) = &mut self.body {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            self.body = Body::Bytes(bytes);
+        }
+        Ok(self)
+    }
+
+    /// Drops the body, keeping the headers it would have been sent with, to answer a
+    /// HEAD request.
+    pub fn without_body(mut self) -> Self {
+        self.body = Body::Omitted(self.body.len());
+        self
+    }
+
+    /// Case-insensitive header lookup.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn status_line(&self) -> String {
+        format!("HTTP/1.1 {} {}", self.status, reason_phrase(self.status))
+    }
+
+    /// Writes the status line, the headers and the body, returning the length of the
+    /// body as sent. Any `Content-Length` or `Transfer-Encoding` header is replaced by
+    /// the framing the body needs.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<u64> {
+        let mut head = self.status_line();
+        head.push_str("\r\n");
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case("content-length")
+                || name.eq_ignore_ascii_case("transfer-encoding")
+            {
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        // 1xx, 204 and 304 responses never have a body
+        if !matches!(self.status, 100..=199 | 204 | 304) {
+            match self.body.len() {
+                Some(length) => head.push_str(&format!("Content-Length: {length}\r\n")),
+                None => head.push_str("Transfer-Encoding: chunked\r\n"),
+            }
+        }
+        head.push_str("\r\n");
+
+        writer.write_all(head.as_bytes())?;
+        let written = match self.body {
+            Body::Bytes(bytes) => {
+                writer.write_all(&bytes)?;
+                bytes.len() as u64
+            }
+            Body::Reader { reader, length } => {
+                let copied = io::copy(&mut reader.take(length), writer)?;
+                if copied != length {
+                    // The Content-Length was already sent, the connection can't be reused
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "body reader ended early",
+                    ));
+                }
+                copied
+            }
+            Body::Stream(reader) => write_chunked(reader, writer)?,
+            Body::Omitted(_) => 0,
+        };
+        writer.flush()?;
+        Ok(written)
+    }
+}
+
+fn write_chunked<W: Write>(mut reader: impl Read, writer: &mut W) -> io::Result<u64> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut written = 0;
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write!(writer, "{read:x}\r\n")?;
+        writer.write_all(&buf[..read])?;
+        writer.write_all(b"\r\n")?;
+        written += read as u64;
+    }
+    writer.write_all(b"0\r\n\r\n")?;
+    Ok(written)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls deflate.
+    Deflate,
+}
+
+impl Encoding {
+    fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn compress_stream(self, reader: impl Read + Send + 'static) -> Body {
+        match self {
+            Encoding::Gzip => Body::Stream(Box::new(GzReader::new(reader, Compression::default()))),
+            Encoding::Deflate => {
+                Body::Stream(Box::new(ZlibReader::new(reader, Compression::default())))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Gzip => write!(f, "gzip"),
+            Encoding::Deflate => write!(f, "deflate"),
+        }
+    }
+}
+
+/// Picks the supported encoding with the highest q-value in an `Accept-Encoding`
+/// header, gzip on ties. `*` stands for the encodings not listed.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut any = None;
+
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default().to_ascii_lowercase();
+        let q = params
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok());
+        let Some(q) = q else { continue };
+
+        match name.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "deflate" => deflate = Some(q),
+            "*" => any = Some(q),
+            _ => {}
+        }
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    match (gzip, deflate) {
+        (g, d) if g > 0.0 && g >= d => Some(Encoding::Gzip),
+        (_, d) if d > 0.0 => Some(Encoding::Deflate),
+        _ => None,
+    }
+}
+
+/// Text compresses well, images and archives are compressed already.
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "CREATED",
+        204 => "NO CONTENT",
+        301 => "MOVED PERMANENTLY",
+        304 => "NOT MODIFIED",
+        400 => "BAD REQUEST",
+        403 => "FORBIDDEN",
+        404 => "NOT FOUND",
+        405 => "METHOD NOT ALLOWED",
+        408 => "REQUEST TIMEOUT",
+        413 => "PAYLOAD TOO LARGE",
+        431 => "REQUEST HEADER FIELDS TOO LARGE",
+        500 => "INTERNAL SERVER ERROR",
+        501 => "NOT IMPLEMENTED",
+        503 => "SERVICE UNAVAILABLE",
+        505 => "HTTP VERSION NOT SUPPORTED",
+        _ => "UNKNOWN",
+    }
+}
+
+#[test]
+fn write_response() {
+    let response = Response::new(404)
+        .with_header("Content-Type", "text/plain")
+        .with_header("Content-Length", "999")
+        .with_body("nope");
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+
+    let mut out = Vec::new();
+    response.write_to(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 404 NOT FOUND\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\nnope"
+    );
+
+    let mut out = Vec::new();
+    Response::new(200)
+        .with_reader(&b"streamed body"[..], 8)
+        .write_to(&mut out)
+        .unwrap();
+    assert_eq!(out, b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nstreamed");
+}
+
+#[test]
+fn compression_and_chunked_bodies() {
+    use crate::request::Limits;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+    assert_eq!(negotiate("deflate, gzip;q=0.5"), Some(Encoding::Deflate));
+    assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+    assert_eq!(negotiate("identity, br"), None);
+    assert_eq!(negotiate("*;q=0"), None);
+
+    let request = |accept: &str| {
+        let raw = format!("GET / HTTP/1.1\r\nAccept-Encoding: {accept}\r\n\r\n");
+        Request::parse(&mut raw.as_bytes(), &Limits::default()).unwrap()
+    };
+    let send = |response: Response| {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let split = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let body = out.split_off(split);
+        (String::from_utf8(out).unwrap(), body)
+    };
+    // The request parser already knows how to undo the chunked framing
+    let dechunk = |body: Vec<u8>| {
+        let mut raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        raw.extend(body);
+        Request::parse(&mut &raw[..], &Limits::default())
+            .unwrap()
+            .body
+    };
+    let text = "compress me, ".repeat(100);
+
+    // In-memory body, gzip
+    let response = Response::new(200)
+        .with_header("Content-Type", "text/plain; charset=utf-8")
+        .with_header("ETag", "\"abc\"")
+        .with_body(text.clone())
+        .compress_for(&request("gzip, deflate"));
+    let (head, body) = send(response);
+    assert!(head.contains("Content-Encoding: gzip\r\n"));
+    assert!(head.contains("Vary: Accept-Encoding\r\n"));
+    assert!(head.contains("ETag: W/\"abc\"\r\n"));
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert!(body.len() < text.len());
+    let mut decoded = String::new();
+    GzDecoder::new(&body[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    // Streamed from a reader, deflate on the fly and sent chunked
+    let response = Response::new(200)
+        .with_header("Content-Type", "text/html")
+        .with_reader(io::Cursor::new(text.clone()), text.len() as u64)
+        .compress_for(&request("deflate"));
+    let (head, body) = send(response);
+    assert!(head.contains("Content-Encoding: deflate\r\n"));
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!head.contains("Content-Length"));
+    let mut decoded = String::new();
+    ZlibDecoder::new(&dechunk(body)[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    // Left alone: binary types, tiny bodies and clients that don't ask for it
+    for (content_type, body, accept) in [
+        ("image/png", text.as_str(), "gzip"),
+        ("text/plain", "tiny", "gzip"),
+        ("text/plain", text.as_str(), "identity"),
+    ] {
+        let response = Response::new(200)
+            .with_header("Content-Type", content_type)
+            .with_body(body)
+            .compress_for(&request(accept));
+        assert_eq!(response.header("content-encoding"), None);
+        assert_eq!(response.body.as_bytes(), Some(body.as_bytes()));
+    }
+
+    // Plain streams are framed as they're read
+    let (head, body) = send(Response::new(200).with_stream(io::Cursor::new(text.clone())));
+    assert_eq!(
+        head,
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"
+    );
+    assert!(body.starts_with(format!("{:x}\r\n", text.len()).as_bytes()));
+    assert_eq!(dechunk(body), text.as_bytes());
+
+    let buffered = Response::new(200)
+        .with_stream(&b"for HTTP/1.0"[..])
+        .buffered()
+        .unwrap();
+    assert_eq!(buffered.body.as_bytes(), Some(&b"for HTTP/1.0"[..]));
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+
+use crate::{
+    request::{Method, Request},
+    response::Response,
+};
+
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    /// `:name`, matches exactly one segment.
+    Param(String),
+    /// `*name`, matches the rest of the path. Only allowed as the last segment.
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    source: String,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+/// Dispatches requests to the handlers registered by method and path pattern.
+///
+/// Patterns are matched in registration order, so more specific routes should be
+/// registered first.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Box<dyn Handler>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: Box::new(|_: &Request| Response::new(404)),
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for `pattern`, e.g. `/users/:id` or `/static/*path`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern doesn't start with `/` or a wildcard isn't the last segment.
+    pub fn route<H: Handler>(mut self, method: Method, pattern: &str, handler: H) -> Self {
+        self.routes.push(Route {
+            method,
+            source: pattern.to_string(),
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler>(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Handler used when no pattern matches the path, 404 by default.
+    pub fn fallback<H: Handler>(mut self, handler: H) -> Self {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    /// Finds the handler for the request, fills `request.params` and `request.route`
+    /// and runs it. A HEAD request with no route of its own goes to the GET one.
+    pub fn dispatch(&self, request: &mut Request) -> Response {
+        let mut allowed = Vec::new();
+        let mut get_for_head = None;
+
+        for route in &self.routes {
+            let Some(params) = match_path(&route.pattern, &request.path) else {
+                continue;
+            };
+            if route.method == Method::Get && request.method == Method::Head {
+                get_for_head.get_or_insert((route, params));
+                continue;
+            }
+            if route.method != request.method {
+                request.route.get_or_insert_with(|| route.source.clone());
+                if !allowed.contains(&route.method) {
+                    allowed.push(route.method);
+                }
+                continue;
+            }
+
+            request.params = params;
+            request.route = Some(route.source.clone());
+            return route.handler.handle(request);
+        }
+
+        if let Some((route, params)) = get_for_head {
+            request.params = params;
+            request.route = Some(route.source.clone());
+            return route.handler.handle(request);
+        }
+        if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+            allowed.push(Method::Head);
+        }
+        if allowed.is_empty() {
+            return self.fallback.handle(request);
+        }
+
+        let allow = allowed
+            .iter()
+            .map(Method::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        Response::new(405).with_header("Allow", &allow)
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    assert!(pattern.starts_with('/'), "pattern must start with '/'");
+
+    let segments = pattern[1..]
+        .split('/')
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(segment.to_string())
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let wildcard = segments
+        .iter()
+        .position(|s| matches!(s, Segment::Wildcard(_)));
+    if let Some(position) = wildcard {
+        assert_eq!(
+            position,
+            segments.len() - 1,
+            "wildcard must be the last segment"
+        );
+    }
+
+    segments
+}
+
+fn match_path(pattern: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut rest = path.strip_prefix('/')?;
+
+    for (i, segment) in pattern.iter().enumerate() {
+        if let Segment::Wildcard(name) = segment {
+            params.insert(name.clone(), rest.to_string());
+            return Some(params);
+        }
+
+        let (current, next) = rest.split_once('/').unwrap_or((rest, ""));
+        match segment {
+            Segment::Static(s) if s == current => {}
+            Segment::Param(name) if !current.is_empty() => {
+                params.insert(name.clone(), current.to_string());
+            }
+            _ => return None,
+        }
+
+        let last = i == pattern.len() - 1;
+        match (last, rest.contains('/')) {
+            (true, true) => return None,
+            (true, false) => return Some(params),
+            (false, true) => rest = next,
+            // The path ran out before the pattern did
+            (false, false) => return match_remaining_wildcard(&pattern[i + 1..], params),
+        }
+    }
+
+    Some(params)
+}
+
+// `/static/*path` also matches `/static`, with an empty `path`
+fn match_remaining_wildcard(
+    pattern: &[Segment],
+    mut params: HashMap<String, String>,
+) -> Option<HashMap<String, String>> {
+    match pattern {
+        [Segment::Wildcard(name)] => {
+            params.insert(name.clone(), String::new());
+            Some(params)
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn router_dispatch() {
+    use crate::request::Limits;
+
+    let router = Router::new()
+        .get("/", |_: &Request| Response::new(200).with_body("index"))
+        .get("/users/:id", |req: &Request| {
+            Response::new(200).with_body(format!("user {}", req.param("id").unwrap()))
+        })
+        .post("/users/:id", |_: &Request| Response::new(201))
+        .get("/users/:id/posts/:post", |req: &Request| {
+            let (id, post) = (req.param("id").unwrap(), req.param("post").unwrap());
+            Response::new(200).with_body(format!("{id}/{post}"))
+        })
+        .get("/static/*path", |req: &Request| {
+            Response::new(200).with_body(req.param("path").unwrap().to_string())
+        })
+        .fallback(|_: &Request| Response::new(404).with_body("custom"));
+
+    let dispatch = |raw: &str| {
+        let mut request = Request::parse(&mut raw.as_bytes(), &Limits::default()).unwrap();
+        router.dispatch(&mut request)
+    };
+
+    let response = dispatch("GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(
+        (response.status, response.body.as_bytes().unwrap()),
+        (200, &b"index"[..])
+    );
+
+    let response = dispatch("GET /users/42?x=1 HTTP/1.1\r\n\r\n");
+    assert_eq!(
+        (response.status, response.body.as_bytes().unwrap()),
+        (200, &b"user 42"[..])
+    );
+
+    let response = dispatch("POST /users/42 HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status, 201);
+
+    let response = dispatch("GET /users/42/posts/7 HTTP/1.1\r\n\r\n");
+    assert_eq!(response.body.as_bytes(), Some(&b"42/7"[..]));
+
+    let response = dispatch("GET /static/css/site.css HTTP/1.1\r\n\r\n");
+    assert_eq!(response.body.as_bytes(), Some(&b"css/site.css"[..]));
+    let response = dispatch("GET /static HTTP/1.1\r\n\r\n");
+    assert_eq!(
+        (response.status, response.body.as_bytes().unwrap()),
+        (200, &b""[..])
+    );
+
+    let response = dispatch("DELETE /users/42 HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("allow"), Some("GET, POST, HEAD"));
+
+    let response = dispatch("HEAD /users/42 HTTP/1.1\r\n\r\n");
+    assert_eq!(
+        (response.status, response.body.as_bytes().unwrap()),
+        (200, &b"user 42"[..])
+    );
+
+    for missing in ["/users", "/users/", "/users/42/posts", "/nope"] {
+        let response = dispatch(&format!("GET {missing} HTTP/1.1\r\n\r\n"));
+        assert_eq!(
+            (response.status, response.body.as_bytes().unwrap()),
+            (404, &b"custom"[..])
+        );
+    }
+}
@@ -0,0 +1,746 @@
+use std::{
+    collections::HashMap,
+    io::{self, prelude::*, BufReader},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::{
+    access_log::AccessLog,
+    metrics::{RequestMetrics, UNMATCHED_ROUTE},
+    pool::{ThreadPool, ThreadPoolBuilder},
+    request::{Limits, Method, ParseError, Request},
+    response::Response,
+    router::Router,
+};
+
+/// How often idle connections check whether the server is shutting down.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// Pause after a failed accept, the cause (like running out of file descriptors)
+/// tends to stick around for a bit.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Time allowed to receive a whole request once its first byte arrived.
+    pub read_timeout: Duration,
+    /// Time a kept-alive connection may sit without a new request before it's closed.
+    pub idle_timeout: Duration,
+    /// Time allowed for each write of the response.
+    pub write_timeout: Duration,
+    /// Time in-flight requests get to finish once shutdown starts, after that their
+    /// sockets are closed under them.
+    pub shutdown_timeout: Duration,
+    /// Compresses text responses with gzip or deflate when the client accepts it.
+    pub compression: bool,
+    pub limits: Limits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            read_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(10),
+            shutdown_timeout: Duration::from_secs(10),
+            compression: true,
+            limits: Limits::default(),
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    router: Arc<Router>,
+    config: Config,
+    pool: ThreadPoolBuilder,
+    observers: Observers,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+    handle: ShutdownHandle,
+    connections: Arc<Connections>,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, router: Router) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let handle = ShutdownHandle {
+            requested: Arc::new(AtomicBool::new(false)),
+            addr: listener.local_addr()?,
+        };
+
+        Ok(Server {
+            listener,
+            router: Arc::new(router),
+            config: Config::default(),
+            pool: ThreadPool::builder()
+                .with_threads(4)
+                .with_thread_name("web_server"),
+            observers: Observers::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            handle,
+            connections: Arc::new(Connections::default()),
+        })
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Number of workers in the `ThreadPool`, 4 by default.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.pool = self.pool.with_threads(threads);
+        self
+    }
+
+    /// Connections waiting for a worker before new ones get a 503, see
+    /// `ThreadPool::with_capacity`.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.pool = self.pool.with_queue_capacity(capacity);
+        self
+    }
+
+    /// Replaces the `ThreadPool` settings altogether, e.g. to let it grow and shrink
+    /// with the load.
+    pub fn with_pool(mut self, pool: ThreadPoolBuilder) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Logs every response, none are logged by default.
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.observers.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Records every request in `metrics`, which can also be served from a route.
+    pub fn with_metrics(mut self, metrics: Arc<RequestMetrics>) -> Self {
+        self.observers.metrics = Some(metrics);
+        self
+    }
+
+    /// Serves HTTPS instead of plain HTTP. Fails if the certificates or keys can't be
+    /// loaded.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: &TlsConfig) -> io::Result<Self> {
+        self.tls = Some(tls.load()?);
+        Ok(self)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.handle.addr
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.handle.clone()
+    }
+
+    /// Accepts connections until shutdown is requested, then waits up to
+    /// `Config::shutdown_timeout` for the in-flight requests before returning.
+    ///
+    /// Failing to accept a connection is logged, not fatal. Should the listener itself
+    /// fail, the in-flight requests are still waited for before returning its error.
+    pub fn run(self) -> io::Result<()> {
+        let pool = self.pool.build();
+        let mut result = Ok(());
+
+        for stream in self.listener.incoming() {
+            if self.handle.is_requested() {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) if is_listener_error(&self.listener, &e) => {
+                    result = Err(e);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Failed to accept a connection: {e}");
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+
+            let id = match self.connections.insert(&stream) {
+                Ok(id) => id,
+                Err(e) => {
+                    eprintln!("Failed to accept a connection: {e}");
+                    continue;
+                }
+            };
+            let connections = Arc::clone(&self.connections);
+            let router = Arc::clone(&self.router);
+            let config = self.config;
+            let observers = self.observers.clone();
+            let requested = Arc::clone(&self.handle.requested);
+            #[cfg(feature = "tls")]
+            let tls = self.tls.clone();
+
+            let job = move || {
+                #[cfg(feature = "tls")]
+                let result = match tls {
+                    Some(tls) => crate::tls::accept(&tls, stream).and_then(|stream| {
+                        handle_connection(stream, &router, &config, &observers, &requested)
+                    }),
+                    None => handle_connection(stream, &router, &config, &observers, &requested),
+                };
+                #[cfg(not(feature = "tls"))]
+                let result = handle_connection(stream, &router, &config, &observers, &requested);
+
+                if let Err(e) = result {
+                    eprintln!("Connection error: {e}");
+                }
+                connections.remove(id);
+            };
+
+            if pool.try_execute(job).is_err() {
+                if let Some(stream) = self.connections.remove(id) {
+                    reject_busy(stream);
+                }
+            }
+        }
+
+        // New connections are refused from here on
+        drop(self.listener);
+
+        if !self.connections.wait_empty(self.config.shutdown_timeout) {
+            eprintln!("Shutdown timeout expired, closing the remaining connections.");
+            self.connections.close_all();
+        }
+
+        // Joins the workers, see ThreadPool's Drop
+        drop(pool);
+        result
+    }
+}
+
+/// Where served requests are reported.
+#[derive(Clone, Default)]
+pub(crate) struct Observers {
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Option<Arc<RequestMetrics>>,
+}
+
+impl Observers {
+    fn observe(
+        &self,
+        client: Option<SocketAddr>,
+        request: Option<&Request>,
+        status: u16,
+        bytes: u64,
+        elapsed: Duration,
+    ) {
+        if let Some(access_log) = &self.access_log {
+            access_log.log(client, request, status, bytes, elapsed);
+        }
+        // Requests that couldn't be parsed have no method or route to count them by
+        if let (Some(metrics), Some(request)) = (&self.metrics, request) {
+            let route = request.route.as_deref().unwrap_or(UNMATCHED_ROUTE);
+            metrics.record(route, &request.method.to_string(), status, elapsed);
+        }
+    }
+}
+
+/// Stops a running `Server`, it can be cloned and sent to other threads.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // accept() has no timeout, a connection of our own wakes it up
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            match addr {
+                SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+                SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        let _ = TcpStream::connect(addr);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// The open connections, kept to close them if they outlive the shutdown timeout.
+#[derive(Default)]
+struct Connections {
+    streams: Mutex<(usize, HashMap<usize, TcpStream>)>,
+    emptied: Condvar,
+}
+
+impl Connections {
+    fn insert(&self, stream: &TcpStream) -> io::Result<usize> {
+        let stream = stream.try_clone()?;
+        let mut guard = self.streams.lock().unwrap();
+        let (next_id, streams) = &mut *guard;
+        *next_id += 1;
+        streams.insert(*next_id, stream);
+        Ok(*next_id)
+    }
+
+    fn remove(&self, id: usize) -> Option<TcpStream> {
+        let mut guard = self.streams.lock().unwrap();
+        let stream = guard.1.remove(&id);
+        if guard.1.is_empty() {
+            self.emptied.notify_all();
+        }
+        stream
+    }
+
+    /// Returns false if there were still connections open after the timeout.
+    fn wait_empty(&self, timeout: Duration) -> bool {
+        let guard = self.streams.lock().unwrap();
+        let (guard, _) = self
+            .emptied
+            .wait_timeout_while(guard, timeout, |(_, streams)| !streams.is_empty())
+            .unwrap();
+        guard.1.is_empty()
+    }
+
+    fn close_all(&self) {
+        for stream in self.streams.lock().unwrap().1.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Serves every request sent over the connection, in order, until the client asks to
+/// close it, a timeout expires, a request can't be parsed or `shutdown` is set.
+///
+/// Pipelined requests are answered one after the other, the bytes following a request
+/// are kept buffered for the next iteration.
+pub(crate) fn handle_connection<C: Connection>(
+    stream: C,
+    router: &Router,
+    config: &Config,
+    observers: &Observers,
+    shutdown: &AtomicBool,
+) -> io::Result<()> {
+    stream.tcp().set_write_timeout(Some(config.write_timeout))?;
+    let client = stream.tcp().peer_addr().ok();
+    let mut reader = BufReader::new(DeadlineReader {
+        stream,
+        deadline: None,
+    });
+
+    let result = serve(&mut reader, client, router, config, observers, shutdown);
+    // The client may be gone already, there's nobody to report a failure to
+    let _ = reader.get_mut().stream.close();
+    result
+}
+
+fn serve<C: Connection>(
+    reader: &mut BufReader<DeadlineReader<C>>,
+    client: Option<SocketAddr>,
+    router: &Router,
+    config: &Config,
+    observers: &Observers,
+    shutdown: &AtomicBool,
+) -> io::Result<()> {
+    loop {
+        // Wait for the next request, a pipelined one is already in the buffer
+        let idle_deadline = Instant::now() + config.idle_timeout;
+        loop {
+            if reader.buffer().is_empty() && shutdown.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            reader.get_mut().deadline = Some(idle_deadline.min(Instant::now() + SHUTDOWN_POLL));
+            match reader.fill_buf() {
+                Ok([]) => return Ok(()),
+                Ok(_) => break,
+                Err(e) if is_timeout(&e) && Instant::now() < idle_deadline => continue,
+                Err(e) if is_timeout(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let started = Instant::now();
+        reader.get_mut().deadline = Some(started + config.read_timeout);
+        let (request, response, keep_alive) = match Request::parse(reader, &config.limits) {
+            Ok(mut request) => {
+                let keep_alive = wants_keep_alive(&request);
+                let mut response = router.dispatch(&mut request);
+                if config.compression {
+                    response = response.compress_for(&request);
+                }
+                // Chunked encoding is HTTP/1.1 only
+                if request.version == "HTTP/1.0" {
+                    response = response.buffered()?;
+                }
+                if request.method == Method::Head {
+                    response = response.without_body();
+                }
+                let keep_alive = keep_alive
+                    && !shutdown.load(Ordering::SeqCst)
+                    && !response
+                        .header("connection")
+                        .is_some_and(|c| c.eq_ignore_ascii_case("close"));
+                (Some(request), response, keep_alive)
+            }
+            Err(ParseError::Closed) => return Ok(()),
+            Err(ParseError::Io(e)) if is_timeout(&e) => (None, Response::new(408), false),
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                eprintln!("Rejecting request: {e}");
+                (None, Response::new(e.status()), false)
+            }
+        };
+
+        let response = match (keep_alive, response.header("connection")) {
+            (_, Some(_)) => response,
+            (true, None) => response.with_header("Connection", "keep-alive"),
+            (false, None) => response.with_header("Connection", "close"),
+        };
+        let status = response.status;
+        // Anything buffered past this request stays in the reader for the next one
+        let bytes = response.write_to(&mut reader.get_mut().stream)?;
+        observers.observe(client, request.as_ref(), status, bytes, started.elapsed());
+
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Answers a connection that didn't fit in the pool's queue.
+fn reject_busy(stream: TcpStream) {
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let response = Response::new(503)
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    if let Err(e) = response.write_to(&mut &stream) {
+        eprintln!("Couldn't reject connection: {e}");
+    }
+}
+
+/// HTTP/1.1 connections are persistent unless told otherwise, HTTP/1.0 ones are not.
+fn wants_keep_alive(request: &Request) -> bool {
+    let has_token = |token: &str| {
+        request
+            .header("connection")
+            .is_some_and(|c| c.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    };
+
+    match request.version.as_str() {
+        "HTTP/1.1" => !has_token("close"),
+        _ => has_token("keep-alive"),
+    }
+}
+
+/// Whether a failed accept means the listener is unusable, rather than a connection
+/// that went away before it was accepted or a shortage of file descriptors.
+fn is_listener_error(listener: &TcpListener, e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::InvalidInput || listener.local_addr().is_err()
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// A stream requests are served over, plain or wrapped in TLS.
+pub(crate) trait Connection: Read + Write {
+    /// The socket underneath, for timeouts and addresses.
+    fn tcp(&self) -> &TcpStream;
+
+    /// Ends the connection cleanly once the last response is written.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+/// Socket reads share a single deadline instead of each getting the full timeout,
+/// so a client trickling one byte at a time can't hold the worker forever.
+struct DeadlineReader<C> {
+    stream: C,
+    deadline: Option<Instant>,
+}
+
+impl<C: Connection> Read for DeadlineReader<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.tcp().set_read_timeout(Some(remaining))?;
+        }
+        self.stream.read(buf)
+    }
+}
+
+#[test]
+fn keep_alive_and_timeouts() {
+    use std::thread;
+
+    let router = Arc::new(Router::new().get("/:name", |req: &Request| {
+        Response::new(200).with_body(req.param("name").unwrap().to_string())
+    }));
+    let config = Config {
+        read_timeout: Duration::from_millis(200),
+        idle_timeout: Duration::from_millis(200),
+        ..Config::default()
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let shutdown = AtomicBool::new(false);
+        for stream in listener.incoming().take(4) {
+            let observers = Observers::default();
+            handle_connection(stream.unwrap(), &router, &config, &observers, &shutdown).unwrap();
+        }
+    });
+
+    // Pipelined requests are answered in order on the same connection
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\nGET /c HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut responses = String::new();
+    client.read_to_string(&mut responses).unwrap();
+    let bodies = responses
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .map(|r| r.rsplit("\r\n\r\n").next().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(bodies, ["a", "b", "c"]);
+    assert_eq!(responses.matches("Connection: keep-alive\r\n").count(), 2);
+    assert_eq!(responses.matches("Connection: close\r\n").count(), 1);
+
+    // HTTP/1.0 closes after one request by default
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"GET /old HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("Connection: close\r\nContent-Length: 3\r\n\r\nold"));
+
+    // An idle connection is closed without a response
+    let mut client = TcpStream::connect(addr).unwrap();
+    let start = Instant::now();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).unwrap();
+    assert!(response.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // A request that never finishes gets a 408
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"GET /slow HTTP/1.1\r\nHost: ").unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 REQUEST TIMEOUT\r\n"));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn graceful_shutdown() {
+    use std::thread;
+
+    let router = Router::new()
+        .get("/", |_: &Request| Response::new(200).with_body("ok"))
+        .get("/slow", |_: &Request| {
+            thread::sleep(Duration::from_millis(300));
+            Response::new(200).with_body("done")
+        });
+    let server = Server::bind("127.0.0.1:0", router).unwrap();
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    let running = thread::spawn(move || server.run());
+
+    // A kept-alive connection sitting idle doesn't hold the shutdown back
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    while !response.ends_with(b"ok") {
+        let mut buf = [0; 1024];
+        let read = idle.read(&mut buf).unwrap();
+        response.extend_from_slice(&buf[..read]);
+    }
+
+    let mut in_flight = TcpStream::connect(addr).unwrap();
+    in_flight.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    handle.shutdown();
+    running.join().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // The in-flight request still got its response, with the connection closed after it
+    let mut response = String::new();
+    in_flight.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("done"));
+
+    let mut response = Vec::new();
+    idle.read_to_end(&mut response).unwrap();
+    assert!(response.is_empty());
+
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn saturated_pool_rejects_connections() {
+    use std::{sync::mpsc, thread};
+
+    let (release, blocked) = mpsc::channel::<()>();
+    let blocked = Mutex::new(blocked);
+    let router = Router::new().get("/block", move |_: &Request| {
+        blocked.lock().unwrap().recv().unwrap();
+        Response::new(200)
+    });
+    let server = Server::bind("127.0.0.1:0", router)
+        .unwrap()
+        .with_threads(1)
+        .with_queue_capacity(1);
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    let running = thread::spawn(move || server.run());
+
+    // One connection keeps the worker busy, the next one waits in the queue
+    let mut busy = TcpStream::connect(addr).unwrap();
+    busy.write_all(b"GET /block HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut queued = TcpStream::connect(addr).unwrap();
+    queued
+        .write_all(b"GET /block HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let mut rejected = TcpStream::connect(addr).unwrap();
+    let mut response = String::new();
+    rejected.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 SERVICE UNAVAILABLE\r\n"));
+    assert!(response.contains("Retry-After: 1\r\n"));
+
+    release.send(()).unwrap();
+    release.send(()).unwrap();
+    for mut client in [busy, queued] {
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    handle.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn access_log_and_metrics() {
+    use crate::access_log::LogFormat;
+    use std::thread;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let log = SharedBuffer::default();
+    let metrics = Arc::new(RequestMetrics::new());
+    let exposed = Arc::clone(&metrics);
+    let router = Router::new()
+        .get("/users/:id", |_: &Request| {
+            Response::new(200).with_body("user")
+        })
+        .get("/metrics", move |_: &Request| exposed.response());
+    let server = Server::bind("127.0.0.1:0", router)
+        .unwrap()
+        .with_access_log(AccessLog::new(log.clone(), LogFormat::Combined))
+        .with_metrics(metrics);
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    let running = thread::spawn(move || server.run());
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .write_all(
+            b"GET /users/1 HTTP/1.1\r\nUser-Agent: test\r\n\r\n\
+              GET /users/2 HTTP/1.1\r\n\r\n\
+              DELETE /users/3 HTTP/1.1\r\n\r\n\
+              GET /nope HTTP/1.1\r\n\r\n\
+              GET /metrics HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut responses = String::new();
+    client.read_to_string(&mut responses).unwrap();
+
+    // The /metrics request itself is recorded after its response is written
+    let exposed = responses.rsplit("\r\n\r\n").next().unwrap();
+    for expected in [
+        "http_requests_total{route=\"/users/:id\",method=\"GET\",status=\"200\"} 2",
+        "http_requests_total{route=\"/users/:id\",method=\"DELETE\",status=\"405\"} 1",
+        "http_requests_total{route=\"unmatched\",method=\"GET\",status=\"404\"} 1",
+        "http_request_duration_seconds_count{route=\"/users/:id\"} 3",
+    ] {
+        assert!(exposed.lines().any(|l| l == expected), "{expected}");
+    }
+
+    handle.shutdown();
+    running.join().unwrap().unwrap();
+
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    let lines = log.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("127.0.0.1 - - ["));
+    assert!(lines[0].contains("] \"GET /users/1 HTTP/1.1\" 200 4 \"-\" \"test\" "));
+    assert!(lines[2].contains("\"DELETE /users/3 HTTP/1.1\" 405 - "));
+    assert!(lines[4].contains("\"GET /metrics HTTP/1.1\" 200 "));
+}
+
+#[test]
+fn accept_errors() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    for kind in [
+        io::ErrorKind::ConnectionAborted,
+        io::ErrorKind::ConnectionReset,
+        io::ErrorKind::Interrupted,
+    ] {
+        assert!(!is_listener_error(&listener, &kind.into()));
+    }
+    // EMFILE
+    let exhausted = io::Error::from_raw_os_error(24);
+    assert!(!is_listener_error(&listener, &exhausted));
+    assert!(is_listener_error(
+        &listener,
+        &io::ErrorKind::InvalidInput.into()
+    ));
+}
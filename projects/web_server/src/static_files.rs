@@ -0,0 +1,290 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    date::{http_date, parse_http_date},
+    request::{Method, Request},
+    response::Response,
+    router::Handler,
+};
+
+/// Serves files from a root directory.
+///
+/// The file is taken from the `path` route parameter when the handler is mounted on
+/// a wildcard route (`/static/*path`), or from the whole request path otherwise.
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index: "index.html".to_string(),
+        }
+    }
+
+    /// File served for directory requests, `index.html` by default.
+    pub fn with_index(mut self, index: &str) -> Self {
+        self.index = index.to_string();
+        self
+    }
+
+    fn resolve(&self, relative: &str) -> Result<PathBuf, u16> {
+        let relative = percent_decode(relative).ok_or(400_u16)?;
+
+        let mut path = self.root.clone();
+        for component in relative.split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => return Err(403),
+                // Windows separators and drive prefixes would escape the root as well
+                c if c.contains('\\') || c.contains(':') || c.contains('\0') => return Err(403),
+                c => path.push(c),
+            }
+        }
+
+        // Symlinks are followed, as long as they end up inside the root
+        let root = self.root.canonicalize().map_err(|_| 404_u16)?;
+        let path = path.canonicalize().map_err(|_| 404_u16)?;
+        if !path.starts_with(&root) {
+            return Err(403);
+        }
+
+        Ok(path)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request) -> Response {
+        if !matches!(request.method, Method::Get | Method::Head) {
+            return Response::new(405).with_header("Allow", "GET, HEAD");
+        }
+
+        let relative = request.param("path").unwrap_or(&request.path);
+        let mut path = match self.resolve(relative) {
+            Ok(path) => path,
+            Err(status) => return Response::new(status),
+        };
+
+        if path.is_dir() {
+            // Relative links in the index only work with the trailing slash
+            if !request.path.ends_with('/') {
+                let location = format!("{}/", request.path);
+                return Response::new(301).with_header("Location", &location);
+            }
+            path = match self.resolve(&format!("{relative}/{}", self.index)) {
+                Ok(path) if path.is_file() => path,
+                _ => return Response::new(404),
+            };
+        }
+
+        match serve_file(&path, request) {
+            Ok(response) => response,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Response::new(404),
+            Err(e) => {
+                eprintln!("Couldn't serve {}: {e}", path.display());
+                Response::new(500)
+            }
+        }
+    }
+}
+
+fn serve_file(path: &Path, request: &Request) -> io::Result<Response> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let modified = metadata.modified()?;
+    let length = metadata.len();
+
+    let mtime = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let etag = format!("\"{length:x}-{:x}\"", mtime.as_nanos());
+    let last_modified = http_date(modified);
+
+    let response = Response::new(200)
+        .with_header("ETag", &etag)
+        .with_header("Last-Modified", &last_modified);
+
+    if is_not_modified(request, &etag, mtime.as_secs()) {
+        return Ok(Response {
+            status: 304,
+            ..response
+        });
+    }
+
+    Ok(response
+        .with_header("Content-Type", content_type(path))
+        .with_reader(file, length))
+}
+
+fn is_not_modified(request: &Request, etag: &str, mtime_secs: u64) -> bool {
+    // If-None-Match takes precedence, If-Modified-Since is ignored when it's present
+    if let Some(tags) = request.header("if-none-match") {
+        return tags.split(',').map(str::trim).any(|tag| {
+            // Weak comparison is used for conditional GETs
+            tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+        });
+    }
+
+    request
+        .header("if-modified-since")
+        .and_then(parse_http_date)
+        .and_then(|since| since.duration_since(UNIX_EPOCH).ok())
+        .is_some_and(|since| mtime_secs <= since.as_secs())
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+#[test]
+fn static_files() {
+    use crate::{request::Limits, router::Router};
+    use std::fs;
+
+    let base = std::env::temp_dir().join(format!("web_server_static_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    let root = base.join("public");
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("index.html"), "<h1>index</h1>").unwrap();
+    fs::write(root.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+    fs::write(root.join("style.css"), "body {}").unwrap();
+    fs::write(root.join("with space.txt"), "spaced").unwrap();
+    fs::write(base.join("secret.txt"), "secret").unwrap();
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(base.join("secret.txt"), root.join("escape.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("style.css"), root.join("inside.css")).unwrap();
+    }
+
+    let router = Router::new().get("/static/*path", StaticFiles::new(&root));
+    let send = |raw: String| {
+        let mut request = Request::parse(&mut raw.as_bytes(), &Limits::default()).unwrap();
+        let mut response = router.dispatch(&mut request);
+        // As the server answers HEAD
+        if request.method == Method::Head {
+            response = response.without_body();
+        }
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let get = |path: &str| send(format!("GET {path} HTTP/1.1\r\n\r\n"));
+
+    let css = get("/static/style.css");
+    assert!(css.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(css.contains("Content-Type: text/css; charset=utf-8\r\n"));
+    assert!(css.ends_with("\r\n\r\nbody {}"));
+
+    let head = send("HEAD /static/style.css HTTP/1.1\r\n\r\n".to_string());
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Length: 7\r\n"));
+    assert!(head.ends_with("\r\n\r\n"));
+    let post = send("POST /static/style.css HTTP/1.1\r\n\r\n".to_string());
+    assert!(post.starts_with("HTTP/1.1 405"));
+    assert!(post.contains("Allow: GET, HEAD\r\n"));
+
+    assert!(get("/static/").ends_with("<h1>index</h1>"));
+    assert!(get("/static/docs/").ends_with("<h1>docs</h1>"));
+    assert!(get("/static/docs").contains("Location: /static/docs/\r\n"));
+    assert!(get("/static/with%20space.txt").ends_with("spaced"));
+    assert!(get("/static/missing.txt").starts_with("HTTP/1.1 404"));
+
+    assert!(get("/static/../secret.txt").starts_with("HTTP/1.1 403"));
+    assert!(get("/static/docs/../../secret.txt").starts_with("HTTP/1.1 403"));
+    assert!(get("/static/%2e%2e/secret.txt").starts_with("HTTP/1.1 403"));
+    #[cfg(unix)]
+    {
+        assert!(get("/static/escape.txt").starts_with("HTTP/1.1 403"));
+        assert!(get("/static/inside.css").ends_with("body {}"));
+    }
+
+    // Conditional requests
+    let etag = css
+        .lines()
+        .find_map(|l| l.strip_prefix("ETag: "))
+        .unwrap()
+        .to_string();
+    let last_modified = css
+        .lines()
+        .find_map(|l| l.strip_prefix("Last-Modified: "))
+        .unwrap()
+        .to_string();
+
+    let cached = send(format!(
+        "GET /static/style.css HTTP/1.1\r\nIf-None-Match: \"other\", {etag}\r\n\r\n"
+    ));
+    assert!(cached.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"));
+    assert!(!cached.contains("Content-Length"));
+
+    let stale =
+        send("GET /static/style.css HTTP/1.1\r\nIf-None-Match: \"other\"\r\n\r\n".to_string());
+    assert!(stale.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let cached = send(format!(
+        "GET /static/style.css HTTP/1.1\r\nIf-Modified-Since: {last_modified}\r\n\r\n"
+    ));
+    assert!(cached.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"));
+    let stale = send(
+        "GET /static/style.css HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+            .to_string(),
+    );
+    assert!(stale.starts_with("HTTP/1.1 200 OK\r\n"));
+    // A date too far out to be one is ignored, not a crash
+    let absurd = send(
+        "GET /static/style.css HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 300000000000 08:49:37 GMT\r\n\r\n"
+            .to_string(),
+    );
+    assert!(absurd.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    fs::remove_dir_all(base).unwrap();
+}
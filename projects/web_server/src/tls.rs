@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::server::Connection;
+
+/// Certificates for an HTTPS `Server`, loaded from PEM files.
+///
+/// The certificate is picked by the server name the client sends (SNI). Clients that
+/// send none, or one without a certificate of its own, get the default one.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    default: (PathBuf, PathBuf),
+    hosts: Vec<(String, PathBuf, PathBuf)>,
+}
+
+impl TlsConfig {
+    /// The default certificate chain and its private key.
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            default: (cert.into(), key.into()),
+            hosts: Vec::new(),
+        }
+    }
+
+    /// A certificate for `host`, which may be a wildcard like `*.example.com`.
+    pub fn with_host(
+        mut self,
+        host: &str,
+        cert: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+    ) -> Self {
+        self.hosts
+            .push((host.to_ascii_lowercase(), cert.into(), key.into()));
+        self
+    }
+
+    /// Reads the certificates and keys.
+    pub(crate) fn load(&self) -> io::Result<Arc<ServerConfig>> {
+        let resolver = SniResolver {
+            default: load_certified_key(&self.default.0, &self.default.1)?,
+            hosts: self
+                .hosts
+                .iter()
+                .map(|(host, cert, key)| Ok((host.clone(), load_certified_key(cert, key)?)))
+                .collect::<io::Result<_>>()?,
+        };
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_data)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+}
+
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = hello
+            .server_name()
+            .map(str::to_ascii_lowercase)
+            .and_then(|name| {
+                // An exact match wins over a wildcard one
+                let wildcard = name
+                    .split_once('.')
+                    .map(|(_, parent)| format!("*.{parent}"));
+                self.hosts.get(&name).or_else(|| self.hosts.get(&wildcard?))
+            })
+            .unwrap_or(&self.default);
+        Some(Arc::clone(key))
+    }
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> io::Result<Arc<CertifiedKey>> {
+    let chain = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<io::Result<Vec<_>>>()?;
+    if chain.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificate in {}",
+            cert.display()
+        )));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+        .ok_or_else(|| invalid_data(format!("no private key in {}", key.display())))?;
+    let key = any_supported_type(&key).map_err(invalid_data)?;
+
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// The handshake happens on the first read or write, under the connection's timeouts.
+pub(crate) fn accept(
+    config: &Arc<ServerConfig>,
+    stream: TcpStream,
+) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+    let connection = ServerConnection::new(Arc::clone(config)).map_err(invalid_data)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+impl Connection for StreamOwned<ServerConnection, TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+}
+
+#[test]
+fn https_with_sni() {
+    use crate::{request::Request, response::Response, router::Router, server::Server};
+    use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore};
+    use std::{fs, io::Read, thread};
+
+    let dir = std::env::temp_dir().join(format!("web_server_tls_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // A self-signed certificate per name, each one its own root for the client
+    let mut roots = RootCertStore::empty();
+    let mut ders = HashMap::new();
+    for name in ["localhost", "api.example.test"] {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        fs::write(dir.join(format!("{name}.crt")), generated.cert.pem()).unwrap();
+        fs::write(
+            dir.join(format!("{name}.key")),
+            generated.key_pair.serialize_pem(),
+        )
+        .unwrap();
+        roots.add(generated.cert.der().clone()).unwrap();
+        ders.insert(name, generated.cert.der().to_vec());
+    }
+
+    let tls = TlsConfig::new(dir.join("localhost.crt"), dir.join("localhost.key")).with_host(
+        "*.example.test",
+        dir.join("api.example.test.crt"),
+        dir.join("api.example.test.key"),
+    );
+    let router = Router::new().get("/", |req: &Request| {
+        Response::new(200).with_body(format!("hello {}", req.header("host").unwrap_or("?")))
+    });
+    let server = Server::bind("127.0.0.1:0", router)
+        .unwrap()
+        .with_tls(&tls)
+        .unwrap();
+    let addr = server.local_addr();
+    let handle = server.shutdown_handle();
+    let running = thread::spawn(move || server.run());
+
+    let client_config = Arc::new(
+        ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+    for name in ["localhost", "api.example.test"] {
+        let server_name = ServerName::try_from(name.to_string()).unwrap();
+        let connection = ClientConnection::new(Arc::clone(&client_config), server_name).unwrap();
+        let mut stream = StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\nHost: {name}\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("hello {name}")));
+
+        // The certificate was picked by the name the client asked for
+        let presented = stream.conn.peer_certificates().unwrap()[0].to_vec();
+        assert_eq!(presented, ders[name]);
+    }
+
+    // Plain HTTP on the TLS port gets nowhere
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response);
+    assert!(!response.starts_with(b"HTTP/1.1 200"));
+
+    handle.shutdown();
+    running.join().unwrap().unwrap();
+
+    assert!(
+        TlsConfig::new(dir.join("missing.crt"), dir.join("missing.key"))
+            .load()
+            .is_err()
+    );
+    fs::remove_dir_all(dir).unwrap();
+}